@@ -4,9 +4,9 @@ use tetris::gamestate::GameState;
 use tetris::timer::*;
 
 use std::collections::HashMap;
-use std::io::{stdout, Read, StdoutLock, Write};
+use std::io::{stdout, BufReader, Read, StdoutLock, Write};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use termion::color;
 use termion::event::{self, parse_event, Event};
@@ -17,9 +17,9 @@ struct TerminalGame<'a> {
     game_state: GameState,
     controls: HashMap<event::Key, Button>,
     stdout: RawTerminal<StdoutLock<'a>>,
-    async_input_reader: std::io::Bytes<AsyncReader>,
-    gravity_timer: Timer,
+    async_input_reader: std::io::Bytes<BufReader<AsyncReader>>,
     update_timer: Timer,
+    last_update: Instant,
 }
 
 impl<'a> Controller for TerminalGame<'a> {
@@ -32,20 +32,25 @@ impl<'a> Controller for TerminalGame<'a> {
 
 impl<'a> GameImplementation for TerminalGame<'a> {
     fn new() -> Self {
+        let game_state = GameState::default();
         Self {
-            game_state: GameState::default(),
+            game_state,
             controls: HashMap::from([
                 (event::Key::Up, Button::RotateClockwise),
                 (event::Key::Left, Button::MoveLeft),
                 (event::Key::Right, Button::MoveRight),
-                (event::Key::Down, Button::MoveDown),
+                (event::Key::Down, Button::SoftDrop),
                 (event::Key::Char(' '), Button::Drop),
+                (event::Key::Char('z'), Button::RotateCounterClockwise),
+                (event::Key::Char('x'), Button::RotateClockwise),
+                (event::Key::Char('c'), Button::Hold),
+                (event::Key::Char('p'), Button::Pause),
                 (event::Key::Char('q'), Button::Quit),
             ]),
             stdout: stdout().lock().into_raw_mode().unwrap(),
-            async_input_reader: async_stdin().bytes(),
-            gravity_timer: Timer::new(Duration::from_millis(1000), Mode::Repeating),
+            async_input_reader: BufReader::new(async_stdin()).bytes(),
             update_timer: Timer::new(Duration::from_millis(17), Mode::Repeating),
+            last_update: Instant::now(),
         }
     }
 
@@ -64,8 +69,8 @@ impl<'a> GameImplementation for TerminalGame<'a> {
         )
         .unwrap();
 
-        self.gravity_timer.start();
         self.update_timer.start();
+        self.last_update = Instant::now();
     }
 
     fn on_update(&mut self) {
@@ -95,17 +100,19 @@ impl<'a> GameImplementation for TerminalGame<'a> {
         }
         
 
-        if self.gravity_timer.finished() {
-            self.game_state.apply_gravity();
-        }
+        // Only whole milliseconds are handed over, the remainder is kept for the next update
+        let elapsed_ms = self.last_update.elapsed().as_millis() as u32;
+        self.last_update += Duration::from_millis(elapsed_ms as u64);
+        self.game_state.advance_gravity(elapsed_ms);
+        self.game_state.tick_lock_delay();
                 
-        self.game_state.on_update();
+        self.game_state.on_update(Duration::from_millis(elapsed_ms as u64));
 
         self.stdout.flush().unwrap();
     }
 
     fn is_running(&self) -> bool {
-        !self.game_state.gameover
+        !self.game_state.is_game_over()
     }
 
     fn run(&mut self) {
@@ -113,7 +120,7 @@ impl<'a> GameImplementation for TerminalGame<'a> {
         while self.is_running() {
             self.on_update();
 
-            if !self.update_timer.finished() {
+            if self.update_timer.finished() == 0 {
                 thread::sleep(self.update_timer.time_left());
                 self.update_timer.update();
             }
@@ -121,7 +128,7 @@ impl<'a> GameImplementation for TerminalGame<'a> {
     }
 
     fn quit(&mut self) {
-        self.game_state.gameover = true;
+        self.game_state.on_button_pressed(Button::Quit);
     }
 }
 
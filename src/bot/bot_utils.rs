@@ -0,0 +1,82 @@
+use crate::grid::Grid;
+
+// Weights of the board features combined by `cost_function`
+pub const AGGREGATE_HEIGHT_WEIGHT: f32 = 0.5;
+pub const BUMPINESS_WEIGHT: f32 = 0.2;
+pub const HOLES_WEIGHT: f32 = 4.0;
+pub const SEMI_HOLES_WEIGHT: f32 = 1.0;
+pub const IPIECE_DEPENDENCY_WEIGHT: f32 = 2.0;
+
+// Weighted sum of the board features, lower is better
+pub fn cost_function<const COLS: usize, const ROWS: usize>(grid: &Grid<COLS, ROWS>) -> f32 {
+    AGGREGATE_HEIGHT_WEIGHT * grid.aggregate_height() as f32
+        + BUMPINESS_WEIGHT * grid.bumpiness() as f32
+        + HOLES_WEIGHT * count_holes(grid) as f32
+        + SEMI_HOLES_WEIGHT * count_semi_holes(grid) as f32
+        + IPIECE_DEPENDENCY_WEIGHT * grid.ipiece_dependency_depth() as f32
+}
+
+// Empty cells with at least one filled cell above them in the same column
+pub fn count_holes<const COLS: usize, const ROWS: usize>(grid: &Grid<COLS, ROWS>) -> i32 {
+    grid.hole_positions().len() as i32
+}
+
+// Empty cells that are open above but have a filled cell in the same row
+// on both sides, with the walls counting as filled. Every column contributes
+// min(left height, right height) - its own height when that is positive.
+pub fn count_semi_holes<const COLS: usize, const ROWS: usize>(grid: &Grid<COLS, ROWS>) -> i32 {
+    let heights = grid.heights(ROWS as i32);
+    (0..COLS)
+        .map(|col| {
+            let left = if col == 0 {
+                ROWS as i32
+            } else {
+                heights[col - 1]
+            };
+            let right = heights.get(col + 1).copied().unwrap_or(ROWS as i32);
+            (left.min(right) - heights[col]).max(0)
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::{GridMap, StandardGrid, GRID_COLUMNS, GRID_ROWS};
+    use crate::piece::PieceKind;
+
+    #[test]
+    fn holes_in_empty_grid() {
+        assert_eq!(count_holes(&StandardGrid::new()), 0);
+    }
+
+    #[test]
+    fn single_overhang_hole() {
+        let mut grid_map: GridMap = [[PieceKind::None; GRID_COLUMNS]; GRID_ROWS];
+        grid_map[0][0] = PieceKind::O;
+        grid_map[1][0] = PieceKind::O;
+        grid_map[1][1] = PieceKind::O;
+        assert_eq!(count_holes(&StandardGrid::from(grid_map)), 1);
+    }
+
+    #[test]
+    fn stacked_column_with_two_holes() {
+        let mut grid_map: GridMap = [[PieceKind::None; GRID_COLUMNS]; GRID_ROWS];
+        grid_map[1][4] = PieceKind::T;
+        grid_map[3][4] = PieceKind::T;
+        assert_eq!(count_holes(&StandardGrid::from(grid_map)), 2);
+    }
+
+    #[test]
+    fn semi_holes_in_deep_well() {
+        let grid = StandardGrid::from_ascii(&["IIII.IIIII", "IIII.IIIII", "IIII.IIIII"]).unwrap();
+        assert_eq!(count_semi_holes(&grid), 3);
+    }
+
+    #[test]
+    fn semi_holes_on_flat_surface() {
+        let grid = StandardGrid::from_ascii(&["IIIIIIIIII", "IIIIIIIIII"]).unwrap();
+        assert_eq!(count_semi_holes(&grid), 0);
+        assert_eq!(count_semi_holes(&StandardGrid::new()), 0);
+    }
+}
@@ -0,0 +1,67 @@
+pub mod bot_utils;
+
+use crate::controls::Button;
+use crate::gamestate::GameState;
+
+use bot_utils::cost_function;
+
+// Buttons turning a freshly spawned piece into each of its rotations
+const ROTATIONS: [&[Button]; 4] = [
+    &[],
+    &[Button::RotateClockwise],
+    &[Button::Rotate180],
+    &[Button::RotateCounterClockwise],
+];
+
+// Buttons that hard drop the active piece where the resulting board has the
+// lowest `cost_function`. Every candidate is tried on a clone of the game, so
+// the returned buttons can be fed straight into `on_button_pressed`.
+pub fn best_move<const COLS: usize, const ROWS: usize>(
+    game_state: &GameState<COLS, ROWS>,
+) -> Vec<Button> {
+    let mut best: Option<(f32, Vec<Button>)> = None;
+    for rotation in ROTATIONS {
+        for direction in [Button::MoveLeft, Button::MoveRight] {
+            let mut gs = game_state.clone();
+            let mut buttons = rotation.to_vec();
+            buttons
+                .iter()
+                .for_each(|button| gs.on_button_pressed(*button));
+            loop {
+                let mut dropped = gs.clone();
+                dropped.on_button_pressed(Button::Drop);
+                if !dropped.is_game_over() {
+                    let cost = cost_function(&dropped.grid);
+                    if best.as_ref().is_none_or(|(best_cost, _)| cost < *best_cost) {
+                        best = Some((cost, [buttons.as_slice(), &[Button::Drop]].concat()));
+                    }
+                }
+                // Keep sliding until the piece hits a wall or the stack
+                let x = gs.active_piece.position.x;
+                gs.on_button_pressed(direction);
+                if gs.active_piece.position.x == x {
+                    break;
+                }
+                buttons.push(direction);
+            }
+        }
+    }
+    best.map_or_else(|| vec![Button::Drop], |(_, buttons)| buttons)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::piece::PieceKind;
+
+    #[test]
+    fn best_move_completes_line() {
+        let mut gs = GameState::from_scenario(&["IIIIII..II"], &[], PieceKind::O);
+        for button in best_move(&gs) {
+            gs.on_button_pressed(button);
+        }
+        assert_eq!(gs.score().lines_cleared, 1);
+        assert_eq!(gs.grid.get_cell(6, 0), PieceKind::O);
+        assert_eq!(gs.grid.get_cell(0, 0), PieceKind::None);
+    }
+}
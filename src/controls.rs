@@ -1,11 +1,20 @@
-#[derive(Clone, Copy, Debug)]
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Button {
     MoveDown,
+    // Like MoveDown, scoring a point for every cell the piece moves
+    SoftDrop,
     MoveLeft,
     MoveRight,
     RotateClockwise,
+    RotateCounterClockwise,
+    Rotate180,
     Drop,
+    Hold,
+    Pause,
     Quit,
+    Restart,
 }
 
 pub trait Controller {
@@ -13,3 +22,66 @@ pub trait Controller {
 
     fn key_to_button(&self, key: Self::Key) -> Option<Button>;
 }
+
+// Layout-agnostic gamepad inputs, named after their position on the pad
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GamepadButton {
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+    South,
+    East,
+    West,
+    North,
+    LeftShoulder,
+    RightShoulder,
+    Start,
+    Select,
+}
+
+#[derive(Debug, Clone)]
+pub struct GamepadController {
+    pub mapping: HashMap<GamepadButton, Button>,
+}
+
+impl Default for GamepadController {
+    fn default() -> Self {
+        Self {
+            mapping: HashMap::from([
+                (GamepadButton::DPadUp, Button::Drop),
+                (GamepadButton::DPadDown, Button::MoveDown),
+                (GamepadButton::DPadLeft, Button::MoveLeft),
+                (GamepadButton::DPadRight, Button::MoveRight),
+                (GamepadButton::South, Button::RotateClockwise),
+                (GamepadButton::East, Button::RotateCounterClockwise),
+                (GamepadButton::North, Button::Rotate180),
+                (GamepadButton::LeftShoulder, Button::Hold),
+                (GamepadButton::Select, Button::Quit),
+            ]),
+        }
+    }
+}
+
+impl Controller for GamepadController {
+    type Key = GamepadButton;
+
+    fn key_to_button(&self, key: Self::Key) -> Option<Button> {
+        self.mapping.get(&key).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gamepad_mapping() {
+        let gamepad = GamepadController::default();
+        assert_eq!(
+            gamepad.key_to_button(GamepadButton::South),
+            Some(Button::RotateClockwise)
+        );
+        assert_eq!(gamepad.key_to_button(GamepadButton::Start), None);
+    }
+}
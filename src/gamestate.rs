@@ -1,251 +1,3613 @@
+use std::collections::HashSet;
 use std::fmt;
+use std::time::Duration;
 
+use rand::{Rng, SeedableRng};
 use termion::color;
 
+use crate::bot::bot_utils::cost_function;
 use crate::controls::Button;
-use crate::grid::{Grid, GRID_COLUMNS, GRID_VISIBLE_ROWS};
-use crate::piece::{self, Piece, PieceDimensions, PieceKind};
-use crate::utils::{Direction, Rotation};
+use crate::grid::{Grid, GRID_COLUMNS, GRID_ROWS, GRID_VISIBLE_ROWS};
+use crate::piece::{self, GridPosition, Piece, PieceDimensions};
+use crate::utils::{self, Direction, SplitMix64};
+
+pub use crate::grid::{Side, StandardGrid};
+pub use crate::piece::PieceKind;
+pub use crate::utils::Rotation;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpinKind {
+    // The piece was rotated into a spot it can't move out of
+    Spin(PieceKind),
+    // A T rotated into place with three of the corners around its center
+    // occupied, but only one of the two corners it points towards
+    TSpinMini,
+    // Like `TSpinMini`, with both corners it points towards occupied, or
+    // any T-spin that needed the last of the wall kicks (the 4th or 5th
+    // position tried for a quarter turn)
+    TSpinFull,
+}
+
+// Outcome of locking the active piece into the grid
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct LockResult {
+    pub lines_cleared: i32,
+    pub spin: Option<SpinKind>,
+    // Garbage lines sent to the opponent
+    pub attack: u32,
+}
+
+impl LockResult {
+    // Difficult clears sent while back-to-back is active add one line
+    fn compute_attack(&self, back_to_back: bool) -> u32 {
+        let base = match (self.spin, self.lines_cleared) {
+            (Some(SpinKind::TSpinFull), lines) => 2 * lines as u32,
+            (_, 2) => 1,
+            (_, 3) => 2,
+            (_, 4) => 4,
+            _ => 0,
+        };
+        base + (back_to_back && self.is_difficult()) as u32
+    }
+
+    fn is_difficult(&self) -> bool {
+        self.lines_cleared == 4
+            || (self.lines_cleared > 0
+                && matches!(self.spin, Some(SpinKind::TSpinMini | SpinKind::TSpinFull)))
+    }
+}
+
+// Target of a piece: its kind, its rotation from spawn and its x position.
+// The piece is hard dropped from the top of the grid in that column.
+#[derive(Debug, Clone, Copy)]
+pub struct Placement {
+    pub kind: PieceKind,
+    pub rotation: Rotation,
+    pub x: i32,
+}
+
+// Glyph and colors used by `GameState::render_with_theme`. Colors are
+// written as is before every cell, so they can hold any escape codes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderTheme {
+    pub glyph: String,
+    // Indexed by `PieceKind as usize`
+    pub colors: [String; 9],
+    pub ghost_color: String,
+}
+
+impl Default for RenderTheme {
+    fn default() -> Self {
+        Self {
+            glyph: piece::BLOCK_STR.to_string(),
+            colors: [
+                PieceKind::I,
+                PieceKind::J,
+                PieceKind::L,
+                PieceKind::O,
+                PieceKind::S,
+                PieceKind::T,
+                PieceKind::Z,
+                PieceKind::Garbage,
+                PieceKind::None,
+            ]
+            .map(PieceKind::color),
+            ghost_color: color::Fg(color::Rgb(150, 150, 150)).to_string(),
+        }
+    }
+}
+
+// How the landing position of the active piece is shown
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GhostStyle {
+    // The whole piece outline
+    Full,
+    // A single marker per column the piece will land in
+    ColumnMarker,
+    Off,
+}
+
+// What happens when a soft drop brings the piece down onto the stack
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SoftDropLock {
+    // Lock right away
+    Instant,
+    // Start the lock delay
+    Delayed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Score {
+    pub points: u32,
+    pub lines_cleared: u32,
+    pub level: u32,
+}
+
+impl Default for Score {
+    fn default() -> Self {
+        Self {
+            points: 0,
+            lines_cleared: 0,
+            level: 1,
+        }
+    }
+}
+
+// Why `GameState::from_string_repr` rejected its input
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    // Not exactly four `;` separated sections
+    WrongSectionCount,
+    InvalidBoard,
+    InvalidPiece(char),
+    InvalidNumber(String),
+    // Not a code produced by `GameState::to_share_code`
+    InvalidShareCode,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Stats {
+    pub pieces_placed: u32,
+    pub total_attack_sent: u32,
+    // Game time handed to `advance_gravity`, pauses excluded
+    pub play_time_ms: u64,
+}
+
+// Something that happened in the game as the result of an input or of game
+// time passing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GameEvent {
+    // The active piece moved or rotated
+    PieceMoved,
+    PieceLocked(PieceKind),
+    LinesCleared(u32),
+    PieceHeld(PieceKind),
+    PieceSpawned(PieceKind),
+    GameOver,
+}
+
+// What a call to `GameState::step` did
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepOutcome {
+    // Time passed without a piece locking
+    Stepped,
+    // A piece locked without clearing lines
+    Locked,
+    // A piece locked and cleared this many lines
+    LineCleared(u32),
+    GameOver,
+}
+
+// Why a game ended. Quitting ends it without a reason.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GameOverReason {
+    // A new piece overlapped the stack where it spawned
+    BlockOut,
+    // A piece locked entirely above the visible field
+    LockOut,
+    // Garbage pushed blocks off the top of the grid
+    TopOut,
+}
+
+// Changes needed to bring an older game state up to date with a newer one
+#[derive(Debug, Clone)]
+pub struct GameStateDelta {
+    // Changed cells as (x, y, new kind)
+    pub cells: Vec<(usize, usize, PieceKind)>,
+    pub active_piece: Piece,
+    pub gameover: bool,
+    pub current_piece_bag: Vec<PieceKind>,
+    pub next_piece_bag: Vec<PieceKind>,
+    pub rng_state: u64,
+    pub score: Score,
+}
 
 #[derive(Debug, Clone)]
-pub struct GameState {
-    pub grid: Grid,
+pub struct GameState<const COLS: usize = GRID_COLUMNS, const ROWS: usize = GRID_ROWS> {
+    pub grid: Grid<COLS, ROWS>,
     pub active_piece: Piece,
     pub gameover: bool,
     pub current_piece_bag: Vec<PieceKind>,
     pub next_piece_bag: Vec<PieceKind>,
+    // How long a piece flashes after locking
+    pub lock_flash_duration: Duration,
+    // Counted down by `on_update`
+    lock_flash_remaining: Duration,
+    pieces_spawned: u64,
+    // Garbage waiting to be inserted, as (lines, hole column)
+    pub pending_garbage: Vec<(usize, usize)>,
+    rng: SplitMix64,
+    last_action_was_rotation: bool,
+    // Index of the wall kick the last rotation used, None if it needed none
+    last_kick: Option<usize>,
+    // Set when the last rotation left a T with three corners occupied
+    last_rotation_was_tspin: bool,
+    // Spawn new pieces at the top of the visible field instead of the buffer rows
+    pub spawn_fully_visible: bool,
+    pub stats: Stats,
+    pub ghost_style: GhostStyle,
+    // Hides the ghost without forgetting `ghost_style`
+    pub show_ghost: bool,
+    // How long cleared rows stay on screen before they are removed. Gravity,
+    // input and the next spawn are held back in the meantime.
+    pub line_clear_delay: Duration,
+    clearing_rows: Vec<usize>,
+    // Time until the clearing rows are removed, counted down by `on_update`
+    line_clear_remaining: Option<Duration>,
+    // Never start a game with an S or Z, which would force an overhang
+    pub no_bad_first_piece: bool,
+    pub hold_piece: Option<PieceKind>,
+    // Holding is allowed once per piece, until the next lock
+    pub hold_used_this_turn: bool,
+    // Hold presses are ignored when false
+    pub hold_enabled: bool,
+    // Swap left and right movement as well as the rotation directions
+    pub mirror_controls: bool,
+    // Consecutive line clearing locks minus one, -1 when there is no combo
+    combo: i32,
+    // Set while consecutive line clears are all difficult (tetrises or T-spins)
+    back_to_back: bool,
+    last_spin: Option<SpinKind>,
+    // Set when the most recent lock cleared lines and left the grid empty
+    last_clear_was_perfect: bool,
+    // Lowest and highest row removed by the last `clear_full_rows`
+    last_cleared_row_span: Option<(usize, usize)>,
+    score: Score,
+    // Time since the last gravity step, carried over between updates
+    gravity_accumulator_ms: u32,
+    // Part of a millisecond handed to `step` but not to gravity yet
+    step_remainder: Duration,
+    // Calls to `tick_lock_delay` a resting piece waits before it locks
+    pub lock_delay_ticks: u32,
+    // Ticks left until the resting piece locks, None while it is falling
+    lock_timer: Option<u32>,
+    // Times the lock delay was restarted by moving the current piece
+    lock_resets: u32,
+    pub soft_drop_lock: SoftDropLock,
+    // Milliseconds between soft drop steps while SoftDrop is held down.
+    // None makes every soft drop step take its own press.
+    pub soft_drop_repeat_ms: Option<u32>,
+    // Set between a SoftDrop press and its release
+    soft_drop_held: bool,
+    // Time since the last repeated soft drop step
+    soft_drop_accumulator_ms: u32,
+    // Freezes gravity, updates and every input except Pause and Quit
+    pub paused: bool,
+    // Every block of a piece covers 2x2 cells and moves two cells per step
+    big_mode: bool,
+    game_over_reason: Option<GameOverReason>,
+    // Set by `empty_with_seed` until the first `spawn_next_piece`. The
+    // active piece is only a placeholder meanwhile.
+    awaiting_spawn: bool,
+    // Start and end position of a hard drop, until the next input
+    last_hard_drop: Option<(GridPosition, GridPosition)>,
+}
+
+const DEFAULT_LOCK_FLASH: Duration = Duration::from_millis(100);
+const MIN_GRAVITY_INTERVAL: Duration = Duration::from_millis(16);
+const LINES_PER_LEVEL: u32 = 10;
+// About half a second at 60 updates per second
+const DEFAULT_LOCK_DELAY_TICKS: u32 = 30;
+const MAX_LOCK_RESETS: u32 = 15;
+const TOPOUT_RISK_SAMPLES: u64 = 16;
+// T-spins using this kick or a later one always count as full
+const UPGRADING_KICK: usize = 2;
+// Bumped whenever the layout written by `to_share_code` changes
+const SHARE_CODE_VERSION: u8 = 1;
+
+// The bag the game starts with, last piece first out
+fn first_bag(rng: &mut SplitMix64, no_bad_first_piece: bool) -> Vec<PieceKind> {
+    let mut bag = piece::gen_piece_bag(rng);
+    while no_bad_first_piece && matches!(bag[bag.len() - 1], PieceKind::S | PieceKind::Z) {
+        bag = piece::gen_piece_bag(rng);
+    }
+    bag.to_vec()
+}
+
+// Hole columns for `lines` lines of garbage, the same for every client
+// given the same seed
+pub fn garbage_pattern(seed: u64, lines: usize) -> Vec<usize> {
+    garbage_holes(seed, lines, GRID_COLUMNS)
+}
+
+// `garbage_pattern` for a grid `columns` wide
+fn garbage_holes(seed: u64, lines: usize, columns: usize) -> Vec<usize> {
+    let mut rng = SplitMix64::seed_from_u64(seed);
+    (0..lines).map(|_| rng.gen_range(0..columns)).collect()
 }
 
 impl Default for GameState {
+    fn default() -> Self {
+        Self::with_seed(rand::random())
+    }
+}
+
+// Configures a `GameState` before the game starts. Anything left unset keeps
+// the value `GameState::default` would use.
+#[derive(Debug, Clone, Copy)]
+pub struct GameStateBuilder<const COLS: usize = GRID_COLUMNS, const ROWS: usize = GRID_ROWS> {
+    seed: Option<u64>,
+    gravity_level: u32,
+    ghost: bool,
+    hold: bool,
+}
+
+impl Default for GameStateBuilder {
     fn default() -> Self {
         Self {
-            grid: Grid::default(),
-            active_piece: Piece::new(rand::random()),
-            gameover: false,
-            current_piece_bag: piece::gen_piece_bag().to_vec(),
-            next_piece_bag: piece::gen_piece_bag().to_vec(),
+            seed: None,
+            gravity_level: 1,
+            ghost: true,
+            hold: true,
+        }
+    }
+}
+
+impl GameStateBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<const COLS: usize, const ROWS: usize> GameStateBuilder<COLS, ROWS> {
+    // Builds a game on a grid `C` columns wide and `R` rows high, the
+    // buffer rows above the visible field included
+    pub fn grid_size<const C: usize, const R: usize>(self) -> GameStateBuilder<C, R> {
+        assert!(
+            C >= 4 && R > GRID_ROWS - GRID_VISIBLE_ROWS,
+            "A {C}x{R} grid is too small for a tetromino"
+        );
+        GameStateBuilder {
+            seed: self.seed,
+            gravity_level: self.gravity_level,
+            ghost: self.ghost,
+            hold: self.hold,
         }
     }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    // Level the game starts on, which sets how fast pieces fall
+    pub fn gravity_level(mut self, level: u32) -> Self {
+        self.gravity_level = level.max(1);
+        self
+    }
+
+    pub fn enable_ghost(mut self, enabled: bool) -> Self {
+        self.ghost = enabled;
+        self
+    }
+
+    pub fn enable_hold(mut self, enabled: bool) -> Self {
+        self.hold = enabled;
+        self
+    }
+
+    pub fn build(self) -> GameState<COLS, ROWS> {
+        let mut gs = GameState::sized_with_seed(self.seed.unwrap_or_else(rand::random));
+        gs.score.level = self.gravity_level;
+        gs.show_ghost = self.ghost;
+        gs.hold_enabled = self.hold;
+        gs
+    }
 }
 
+// Constructors for the standard 10x24 board. Other sizes start from
+// `sized_with_seed` or `GameStateBuilder::grid_size`.
 impl GameState {
-    pub fn apply_gravity(&mut self) {
-        match self.distance_to_drop() {
-            0 => self.freeze_piece(),
-            _ => self.active_piece.move_piece(Direction::Down),
+    // A new game whose pieces all come from a generator seeded with `seed`,
+    // so two games with the same seed play out the same pieces
+    pub fn with_seed(seed: u64) -> Self {
+        Self::sized_with_seed(seed)
+    }
+
+    // Like `with_seed`, but nothing spawns until `spawn_next_piece` is called.
+    // The pieces then come in the same order as in `with_seed`.
+    pub fn empty_with_seed(seed: u64) -> Self {
+        let mut gs = Self::with_seed(seed);
+        gs.current_piece_bag.push(gs.active_piece.kind);
+        gs.pieces_spawned = 0;
+        gs.awaiting_spawn = true;
+        gs
+    }
+
+    // Builds a game from an ASCII board (see `StandardGrid::from_ascii`), the pieces
+    // that should spawn next and the kind of the active piece.
+    pub fn from_scenario(board: &[&str], queue: &[PieceKind], active: PieceKind) -> GameState {
+        let grid = StandardGrid::from_ascii(board).expect("Invalid scenario board");
+        let mut gs = GameState {
+            grid,
+            ..Default::default()
+        };
+        gs.active_piece = gs.spawn_piece(active);
+        gs.set_queue(queue);
+        gs
+    }
+
+    // Restores a game written by `to_string_repr`. Fields that aren't part of
+    // the text get their default values.
+    pub fn from_string_repr(s: &str) -> Result<GameState, ParseError> {
+        let sections: Vec<&str> = s.split(';').collect();
+        let [board, active, current, next] = sections[..] else {
+            return Err(ParseError::WrongSectionCount);
+        };
+        let rows: Vec<&str> = board.split('/').collect();
+        if rows.len() != GRID_ROWS {
+            return Err(ParseError::InvalidBoard);
+        }
+        let grid = StandardGrid::from_ascii(&rows).ok_or(ParseError::InvalidBoard)?;
+        let piece_kind = |c: char| match PieceKind::from_char(c) {
+            Some(kind) if piece::PIECE_VEC.contains(&kind) => Ok(kind),
+            _ => Err(ParseError::InvalidPiece(c)),
+        };
+        let bag = |bag: &str| bag.chars().map(piece_kind).collect::<Result<Vec<_>, _>>();
+        let number = |n: &str| {
+            n.parse::<i32>()
+                .map_err(|_| ParseError::InvalidNumber(n.into()))
+        };
+
+        let fields: Vec<&str> = active.split(',').collect();
+        let [kind, rotation, x, y] = fields[..] else {
+            return Err(ParseError::WrongSectionCount);
+        };
+        let mut kind_chars = kind.chars();
+        let (Some(kind), None) = (kind_chars.next(), kind_chars.next()) else {
+            return Err(ParseError::InvalidPiece(kind.chars().next().unwrap_or(' ')));
+        };
+        let mut active_piece = Piece::new(piece_kind(kind)?);
+        active_piece.rotate(Rotation::from(number(rotation)?));
+        active_piece.position.x = number(x)?;
+        active_piece.position.y = number(y)?;
+
+        let mut gs = GameState {
+            grid,
+            current_piece_bag: bag(current)?,
+            next_piece_bag: bag(next)?,
+            ..Default::default()
+        };
+        active_piece.piece_id = gs.active_piece.piece_id;
+        gs.active_piece = active_piece;
+        Ok(gs)
+    }
+
+    // Restores a game written by `to_share_code`. Like `from_string_repr`,
+    // anything not in the code gets its default value.
+    pub fn from_share_code(code: &str) -> Result<GameState, ParseError> {
+        let bytes = utils::decode_base64_url(code).ok_or(ParseError::InvalidShareCode)?;
+        let mut bytes = bytes.iter().copied();
+        let mut byte = || bytes.next().ok_or(ParseError::InvalidShareCode);
+        let kind = |index: u8| PieceKind::from_index(index).ok_or(ParseError::InvalidShareCode);
+        // Cells can also be garbage or empty, pieces only a tetromino
+        let tetromino = |index: u8| {
+            Some(kind(index)?)
+                .filter(|kind| piece::PIECE_VEC.contains(kind))
+                .ok_or(ParseError::InvalidShareCode)
+        };
+        if byte()? != SHARE_CODE_VERSION {
+            return Err(ParseError::InvalidShareCode);
         }
+        let mut gs = GameState::default();
+        for pair in gs.grid.grid_map.as_flattened_mut().chunks_mut(2) {
+            let packed = byte()?;
+            pair[0] = kind(packed >> 4)?;
+            pair[1] = kind(packed & 0xf)?;
+        }
+        let mut active_piece = Piece::new(tetromino(byte()?)?);
+        active_piece.rotate(Rotation::from(byte()? as i32));
+        active_piece.position.x = byte()? as i8 as i32;
+        active_piece.position.y = byte()? as i8 as i32;
+        active_piece.piece_id = gs.active_piece.piece_id;
+        gs.active_piece = active_piece;
+        gs.hold_piece = match byte()? {
+            u8::MAX => None,
+            index => Some(tetromino(index)?),
+        };
+        gs.hold_used_this_turn = byte()? != 0;
+        gs.back_to_back = byte()? != 0;
+        for bag in [&mut gs.current_piece_bag, &mut gs.next_piece_bag] {
+            let len = byte()?;
+            *bag = (0..len)
+                .map(|_| tetromino(byte()?))
+                .collect::<Result<_, _>>()?;
+        }
+        let mut word =
+            || -> Result<[u8; 4], ParseError> { Ok([byte()?, byte()?, byte()?, byte()?]) };
+        gs.combo = i32::from_le_bytes(word()?);
+        gs.score.points = u32::from_le_bytes(word()?);
+        gs.score.lines_cleared = u32::from_le_bytes(word()?);
+        gs.score.level = u32::from_le_bytes(word()?);
+        let state = [word()?, word()?].concat();
+        gs.rng
+            .set_state(u64::from_le_bytes(state.try_into().unwrap()));
+        Ok(gs)
     }
 
-    pub fn freeze_piece(&mut self) {
-        let (x, y) = (self.active_piece.position.x, self.active_piece.position.y);
-        if self.active_piece.y_min() >= GRID_VISIBLE_ROWS as i32 {
-            self.gameover = true;
-        } else {
-            self.active_piece
-                .piece_dimensions
-                .piece_map
-                .iter()
-                .for_each(|(px, py)| {
-                    self.grid.set_cell(x + px, y + py, self.active_piece.kind);
-                });
-            let new_piece_kind = self.current_piece_bag.pop().unwrap_or_else(|| {
-                self.current_piece_bag =
-                    std::mem::replace(&mut self.next_piece_bag, piece::gen_piece_bag().to_vec());
-                self.current_piece_bag.pop().unwrap()
-            });
-            let new_piece = Piece::new(new_piece_kind);
-            if self.grid.overlaps(&new_piece) {
-                self.gameover = true;
-            } else {
-                self.active_piece = new_piece;
-            }
+    // Copy of the settled cells in the visible rows, floor first and without
+    // the active piece
+    pub fn visible_field(&self) -> [[PieceKind; GRID_COLUMNS]; GRID_VISIBLE_ROWS] {
+        let mut field = [[PieceKind::None; GRID_COLUMNS]; GRID_VISIBLE_ROWS];
+        field.copy_from_slice(&self.grid.grid_map[..GRID_VISIBLE_ROWS]);
+        field
+    }
+}
+
+impl<const COLS: usize, const ROWS: usize> GameState<COLS, ROWS> {
+    const VISIBLE_ROWS: usize = Grid::<COLS, ROWS>::VISIBLE_ROWS;
+
+    // `with_seed` for any grid size, e.g. `GameState::<16, 30>::sized_with_seed`
+    pub fn sized_with_seed(seed: u64) -> Self {
+        let mut rng = SplitMix64::seed_from_u64(seed);
+        let no_bad_first_piece = true;
+        let mut current_piece_bag = first_bag(&mut rng, no_bad_first_piece);
+        Self {
+            grid: Grid::new(),
+            active_piece: Piece::spawned_in(
+                current_piece_bag.pop().unwrap(),
+                COLS,
+                Self::VISIBLE_ROWS,
+            ),
+            gameover: false,
+            current_piece_bag,
+            next_piece_bag: piece::gen_piece_bag(&mut rng).to_vec(),
+            lock_flash_duration: DEFAULT_LOCK_FLASH,
+            lock_flash_remaining: Duration::ZERO,
+            pieces_spawned: 1,
+            pending_garbage: Vec::new(),
+            rng,
+            last_action_was_rotation: false,
+            last_kick: None,
+            last_rotation_was_tspin: false,
+            spawn_fully_visible: false,
+            stats: Stats::default(),
+            ghost_style: GhostStyle::Full,
+            show_ghost: true,
+            line_clear_delay: Duration::ZERO,
+            clearing_rows: Vec::new(),
+            line_clear_remaining: None,
+            no_bad_first_piece,
+            hold_piece: None,
+            hold_used_this_turn: false,
+            hold_enabled: true,
+            mirror_controls: false,
+            combo: -1,
+            back_to_back: false,
+            last_spin: None,
+            last_clear_was_perfect: false,
+            last_cleared_row_span: None,
+            score: Score::default(),
+            gravity_accumulator_ms: 0,
+            step_remainder: Duration::ZERO,
+            lock_delay_ticks: DEFAULT_LOCK_DELAY_TICKS,
+            lock_timer: None,
+            lock_resets: 0,
+            soft_drop_lock: SoftDropLock::Delayed,
+            soft_drop_repeat_ms: None,
+            soft_drop_held: false,
+            soft_drop_accumulator_ms: 0,
+            paused: false,
+            last_hard_drop: None,
+            big_mode: false,
+            game_over_reason: None,
+            awaiting_spawn: false,
         }
     }
 
-    pub fn clear_full_rows(&mut self) {
-        let mut rows_to_clear: i32 = 0;
-        let mut new_gs = self.clone();
-        let drop_amounts: Vec<_> = self
+    pub fn is_game_over(&self) -> bool {
+        self.gameover
+    }
+
+    pub fn game_over_reason(&self) -> Option<GameOverReason> {
+        self.game_over_reason
+    }
+
+    fn end_game(&mut self, reason: GameOverReason) {
+        self.gameover = true;
+        self.game_over_reason = Some(reason);
+    }
+
+    pub fn has_active_piece(&self) -> bool {
+        !self.awaiting_spawn
+    }
+
+    // Starts a new game in place. Configuration options are kept.
+    pub fn reset(&mut self) {
+        self.grid.grid_map = [[PieceKind::None; COLS]; ROWS];
+        self.gameover = false;
+        self.game_over_reason = None;
+        self.current_piece_bag = first_bag(&mut self.rng, self.no_bad_first_piece);
+        self.next_piece_bag.clear();
+        self.next_piece_bag
+            .extend_from_slice(&piece::gen_piece_bag(&mut self.rng));
+        self.lock_flash_remaining = Duration::ZERO;
+        self.pending_garbage.clear();
+        self.last_action_was_rotation = false;
+        self.last_kick = None;
+        self.last_rotation_was_tspin = false;
+        self.stats = Stats::default();
+        self.clearing_rows.clear();
+        self.line_clear_remaining = None;
+        self.hold_piece = None;
+        self.hold_used_this_turn = false;
+        self.combo = -1;
+        self.back_to_back = false;
+        self.last_spin = None;
+        self.last_clear_was_perfect = false;
+        self.last_cleared_row_span = None;
+        self.score = Score::default();
+        self.gravity_accumulator_ms = 0;
+        self.step_remainder = Duration::ZERO;
+        self.lock_timer = None;
+        self.lock_resets = 0;
+        self.soft_drop_held = false;
+        self.soft_drop_accumulator_ms = 0;
+        self.paused = false;
+        self.last_hard_drop = None;
+        self.awaiting_spawn = false;
+        self.pieces_spawned = 0;
+        let kind = self.next_piece_kind();
+        self.active_piece = self.spawn_piece(kind);
+    }
+
+    // Empties the grid for practice, keeping the score, the stats and the
+    // pieces. The active piece stays where it is and starts falling again.
+    pub fn clear_board(&mut self) {
+        self.grid.grid_map = [[PieceKind::None; COLS]; ROWS];
+        self.clearing_rows.clear();
+        self.line_clear_remaining = None;
+        self.lock_timer = None;
+        self.lock_resets = 0;
+    }
+
+    // The next `count` pieces in spawn order, without drawing them. Only the
+    // two known bags can be previewed, so fewer pieces may be returned.
+    pub fn preview(&self, count: usize) -> Vec<PieceKind> {
+        self.current_piece_bag
+            .iter()
+            .rev()
+            .chain(self.next_piece_bag.iter().rev())
+            .take(count)
+            .copied()
+            .collect()
+    }
+
+    // Makes `queue` the next pieces to spawn, after which the bags continue
+    pub fn set_queue(&mut self, queue: &[PieceKind]) {
+        assert!(
+            queue.iter().all(|kind| piece::PIECE_VEC.contains(kind)),
+            "Queue contains invalid pieces: {:?}",
+            queue
+        );
+        self.current_piece_bag = queue.iter().rev().copied().collect();
+    }
+
+    // Compact text form of the board, the active piece and both bags:
+    // `<rows top to bottom, '/' separated>;<kind>,<rotation>,<x>,<y>;<current bag>;<next bag>`
+    // where every cell and piece is written as in `PieceKind::to_char`.
+    // Everything else (score, statistics, options) is not included.
+    pub fn to_string_repr(&self) -> String {
+        let rows: Vec<String> = self
             .grid
-            .widths()
+            .grid_map
             .iter()
-            .enumerate()
-            .map(|(row, w)| {
-                if *w == GRID_COLUMNS as i32 {
-                    new_gs.grid.clear_row(row);
-                    rows_to_clear += 1;
-                    0
-                } else {
-                    rows_to_clear
-                }
-            })
+            .rev()
+            .map(|row| row.iter().map(|kind| kind.to_char()).collect())
             .collect();
-        drop_amounts
-            .into_iter()
-            .enumerate()
-            .filter(|(_, drop_amt)| *drop_amt > 0)
-            .for_each(|(row, drop_amt)| {
-                (0..GRID_COLUMNS).for_each(|col| {
-                    new_gs.grid.set_cell(
-                        col as i32,
-                        row as i32 - drop_amt,
-                        self.grid.get_cell(col as i32, row as i32),
-                    )
-                })
-            });
-        *self = new_gs;
+        let bag = |bag: &[PieceKind]| bag.iter().map(|kind| kind.to_char()).collect::<String>();
+        format!(
+            "{};{},{},{},{};{};{}",
+            rows.join("/"),
+            self.active_piece.kind.to_char(),
+            self.active_piece.rotation as i32,
+            self.active_piece.position.x,
+            self.active_piece.position.y,
+            bag(&self.current_piece_bag),
+            bag(&self.next_piece_bag)
+        )
     }
 
-    pub fn distance_to_drop(&self) -> i32 {
-        let (x, y) = (self.active_piece.position.x, self.active_piece.position.y);
-        let xmin = PieceDimensions::x_min(self.active_piece.piece_dimensions.piece_map);
-        (0..self.active_piece.piece_dimensions.width)
-            .filter(|w| 0 <= (x + w + xmin) && (x + w + xmin) < GRID_COLUMNS as i32)
-            .map(|w| {
-                self.active_piece.piece_dimensions.skirt[w as usize] + y
-                    - self
-                        .grid
-                        .heights(self.active_piece.piece_dimensions.skirt[w as usize] + y)
-                        [(x + w + xmin) as usize]
-            })
-            .min()
-            .unwrap()
+    // Compact URL-safe token holding the board, the pieces, the hold slot,
+    // score, combo, back-to-back and the generator state
+    pub fn to_share_code(&self) -> String {
+        let mut bytes = vec![SHARE_CODE_VERSION];
+        let cells = self.grid.grid_map.as_flattened();
+        // Grids with an odd number of cells pad the last byte with an empty cell
+        bytes.extend(cells.chunks(2).map(|pair| {
+            let low = pair.get(1).copied().unwrap_or(PieceKind::None);
+            (pair[0] as u8) << 4 | low as u8
+        }));
+        bytes.extend([
+            self.active_piece.kind as u8,
+            self.active_piece.rotation as u8,
+            self.active_piece.position.x as i8 as u8,
+            self.active_piece.position.y as i8 as u8,
+            self.hold_piece.map_or(u8::MAX, |kind| kind as u8),
+            self.hold_used_this_turn as u8,
+            self.back_to_back as u8,
+        ]);
+        for bag in [&self.current_piece_bag, &self.next_piece_bag] {
+            bytes.push(bag.len() as u8);
+            bytes.extend(bag.iter().map(|kind| *kind as u8));
+        }
+        bytes.extend(self.combo.to_le_bytes());
+        bytes.extend(self.score.points.to_le_bytes());
+        bytes.extend(self.score.lines_cleared.to_le_bytes());
+        bytes.extend(self.score.level.to_le_bytes());
+        bytes.extend(self.rng.state().to_le_bytes());
+        utils::encode_base64_url(&bytes)
     }
 
-    pub fn drop_piece(&mut self) {
-        self.active_piece.position.y -= self.distance_to_drop();
-        self.freeze_piece();
+    // Cells of the active piece to draw, none before the first spawn
+    fn drawn_piece_cells(&self) -> Vec<(i32, i32)> {
+        if self.awaiting_spawn {
+            return Vec::new();
+        }
+        self.piece_cells(&self.active_piece)
     }
 
-    pub fn on_update(&mut self) {
-        self.clear_full_rows();
+    // The visible board as text without any escape codes, top row first.
+    // Cells use `PieceKind::to_char` and the ghost piece is drawn as '#'.
+    pub fn render_plain(&self) -> String {
+        let ghost_cells = self.ghost_cells();
+        let piece_cells = self.drawn_piece_cells();
+        let mut out = String::new();
+        for y in (0..Self::VISIBLE_ROWS as i32).rev() {
+            for x in 0..COLS as i32 {
+                if piece_cells.contains(&(x, y)) {
+                    out.push(self.active_piece.kind.to_char());
+                } else if ghost_cells.contains(&(x, y)) {
+                    out.push('#');
+                } else {
+                    out.push(self.grid.get_cell(x, y).to_char());
+                }
+            }
+            out.push('\n');
+        }
+        out
     }
 
-    fn is_valid_move(&self, dir: Direction) -> bool {
-        let (dx, dy): (i32, i32) = match dir {
-            Direction::Left => (-1, 0),
-            Direction::Right => (1, 0),
-            Direction::Down => (0, -1),
-        };
-        for (rx, ry) in self.active_piece.piece_dimensions.piece_map {
-            let (x, y) = (
-                self.active_piece.position.x + rx + dx,
-                self.active_piece.position.y + ry + dy,
-            );
-            if !(Grid::is_within_bounds(x, y) && self.grid.get_cell(x, y) == PieceKind::None) {
-                return false;
+    // The visible board drawn with the glyph and colors of `theme`, top row
+    // first and with raw terminal line endings
+    pub fn render_with_theme(&self, theme: &RenderTheme) -> String {
+        let ghost_cells = self.ghost_cells();
+        let piece_cells = self.drawn_piece_cells();
+        let mut out = String::new();
+        for y in (0..Self::VISIBLE_ROWS as i32).rev() {
+            for x in 0..COLS as i32 {
+                let color = if piece_cells.contains(&(x, y)) {
+                    &theme.colors[self.active_piece.kind as usize]
+                } else if ghost_cells.contains(&(x, y)) {
+                    &theme.ghost_color
+                } else {
+                    &theme.colors[self.grid.get_cell(x, y) as usize]
+                };
+                out.push_str(color);
+                out.push_str(&theme.glyph);
             }
+            out.push_str("\r\n");
         }
-        true
+        out
     }
 
-    fn try_move(&mut self, dir: Direction) {
-        if self.is_valid_move(dir) {
-            self.active_piece.move_piece(dir)
+    pub fn apply_gravity(&mut self) {
+        if self.paused || self.awaiting_spawn || !self.clearing_rows.is_empty() {
+            return;
+        }
+        match self.distance_to_drop() {
+            0 if self.lock_delay_ticks == 0 => {
+                self.freeze_piece();
+            }
+            0 => {
+                self.lock_timer.get_or_insert(self.lock_delay_ticks);
+            }
+            _ => {
+                self.shift_active_piece(Direction::Down);
+                self.last_action_was_rotation = false;
+            }
         }
     }
 
-    fn is_valid_rotation(&self, rot: Rotation, offset: (i32, i32)) -> bool {
-        let rotated_piecemap =
-            self.active_piece.rotated_pieces[(self.active_piece.rotation + rot) as usize];
-
-        for (rx, ry) in rotated_piecemap {
-            let (x, y) = (
-                self.active_piece.position.x + rx + offset.0,
-                self.active_piece.position.y + ry + offset.1,
-            );
-            if !(Grid::is_within_bounds(x, y) && self.grid.get_cell(x, y) == PieceKind::None) {
-                return false;
+    pub fn freeze_piece(&mut self) -> LockResult {
+        let mut result = LockResult::default();
+        let cells = self.piece_cells(&self.active_piece);
+        if cells.iter().all(|(_, y)| *y >= Self::VISIBLE_ROWS as i32) {
+            self.end_game(GameOverReason::LockOut);
+        } else {
+            result.spin = self.detect_spin();
+            self.last_spin = result.spin;
+            let back_to_back = self.back_to_back;
+            cells
+                .iter()
+                .for_each(|(x, y)| self.grid.set_cell(*x, *y, self.active_piece.kind));
+            self.lock_flash_remaining = self.lock_flash_duration;
+            let full_rows = self.grid.full_rows();
+            if self.line_clear_delay.is_zero() || full_rows.is_empty() {
+                result.lines_cleared = self.clear_full_rows();
+            } else {
+                result.lines_cleared = full_rows.len() as i32;
+                self.clearing_rows = full_rows;
+            }
+            if result.lines_cleared == 0 {
+                // T-spins score even without clearing anything
+                let tspin_points = match result.spin {
+                    Some(SpinKind::TSpinFull) => 400,
+                    Some(SpinKind::TSpinMini) => 100,
+                    _ => 0,
+                };
+                self.score.points += tspin_points * self.score.level;
+            }
+            result.attack = result.compute_attack(back_to_back);
+            self.stats.pieces_placed += 1;
+            self.stats.total_attack_sent += result.attack;
+            if self.clearing_rows.is_empty() {
+                self.spawn_next_piece();
+            } else {
+                self.line_clear_remaining = Some(self.line_clear_delay);
             }
         }
-        true
+        result
     }
 
-    fn try_rotate(&mut self, rot: Rotation) {
-        let transition = (
-            self.active_piece.rotation,
-            (self.active_piece.rotation + rot),
-        );
+    // Replaces the active piece with the next one in the queue
+    pub fn spawn_next_piece(&mut self) {
+        let new_piece_kind = self.next_piece_kind();
+        self.spawn_active_piece(new_piece_kind);
+        self.hold_used_this_turn = false;
+    }
 
-        let offset_list = match self.active_piece.kind {
-            PieceKind::I => match transition {
-                (Rotation::Rot0, Rotation::Rot90) => [(-2, 0), (1, 0), (-2, -1), (1, 2)],
-                (Rotation::Rot90, Rotation::Rot0) => [(2, 0), (-1, 0), (2, 1), (-1, -2)],
-                (Rotation::Rot90, Rotation::Rot180) => [(-1, 0), (2, 0), (-1, 2), (2, -1)],
-                (Rotation::Rot180, Rotation::Rot90) => [(1, 0), (-2, 0), (1, -2), (-2, 1)],
-                (Rotation::Rot180, Rotation::Rot270) => [(2, 0), (-1, 0), (2, 1), (-1, -2)],
-                (Rotation::Rot270, Rotation::Rot180) => [(-2, 0), (1, 0), (-2, -1), (1, 2)],
-                (Rotation::Rot270, Rotation::Rot0) => [(1, 0), (-2, 0), (1, -2), (-2, 1)],
-                (Rotation::Rot0, Rotation::Rot270) => [(-1, 0), (2, 0), (-1, 2), (2, -1)],
-                _ => unreachable!(),
-            },
-            _ => match transition {
-                (Rotation::Rot0, Rotation::Rot90) => [(-1, 0), (-1, 1), (0, -2), (-1, -2)],
-                (Rotation::Rot90, Rotation::Rot0) => [(1, 0), (1, -1), (0, 2), (1, 2)],
-                (Rotation::Rot90, Rotation::Rot180) => [(1, 0), (1, -1), (0, 2), (1, 2)],
-                (Rotation::Rot180, Rotation::Rot90) => [(-1, 0), (-1, 1), (0, -2), (-1, -2)],
-                (Rotation::Rot180, Rotation::Rot270) => [(1, 0), (1, 1), (0, -2), (1, -2)],
-                (Rotation::Rot270, Rotation::Rot180) => [(-1, 0), (-1, -1), (0, 2), (-1, 2)],
-                (Rotation::Rot270, Rotation::Rot0) => [(-1, 0), (-1, -1), (0, 2), (-1, 2)],
-                (Rotation::Rot0, Rotation::Rot270) => [(1, 0), (1, 1), (0, -2), (1, -2)],
-                _ => unreachable!(),
-            },
-        };
-        if self.is_valid_rotation(rot, (0, 0)) {
-            self.active_piece.rotate(rot)
+    fn spawn_active_piece(&mut self, kind: PieceKind) {
+        let new_piece = self.spawn_piece(kind);
+        if !self.fits(&new_piece) {
+            self.end_game(GameOverReason::BlockOut);
         } else {
-            for offset in offset_list {
-                if self.is_valid_rotation(rot, offset) {
-                    self.active_piece.position.x += offset.0;
-                    self.active_piece.position.y += offset.1;
-                    self.active_piece.rotate(rot);
-                    break;
-                }
-            }
-        };
+            self.active_piece = new_piece;
+            self.awaiting_spawn = false;
+            self.last_action_was_rotation = false;
+            self.lock_timer = None;
+            self.lock_resets = 0;
+        }
     }
 
-    pub fn on_button_pressed(&mut self, button: Button) {
-        match button {
-            Button::Quit => self.gameover = true,
-            Button::MoveDown => self.try_move(Direction::Down),
-            Button::MoveLeft => self.try_move(Direction::Left),
-            Button::MoveRight => self.try_move(Direction::Right),
-            Button::Drop => self.drop_piece(),
-            Button::RotateClockwise => self.try_rotate(Rotation::Rot90),
+    // Swaps the active piece into the hold slot, bringing back the previously
+    // held piece or the next one in the queue if the slot was empty.
+    fn hold(&mut self) {
+        if !self.hold_enabled || self.hold_used_this_turn {
+            return;
+        }
+        let new_kind = match self.hold_piece.replace(self.active_piece.kind) {
+            Some(kind) => kind,
+            None => self.next_piece_kind(),
         };
+        self.spawn_active_piece(new_kind);
+        self.hold_used_this_turn = true;
     }
-}
 
-impl fmt::Display for GameState {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let ydrop = self.distance_to_drop();
-        for y in (0..GRID_VISIBLE_ROWS).rev() {
-            for x in 0..GRID_COLUMNS {
-                let rel_x = x as i32 - self.active_piece.position.x;
-                let rel_y = y as i32 - self.active_piece.position.y;
-
-                if self
-                    .active_piece
-                    .piece_dimensions
-                    .piece_map
-                    .contains(&(rel_x, rel_y))
-                {
-                    write!(f, "{}", self.active_piece.kind)?;
-                } else if self
-                    .active_piece
-                    .piece_dimensions
-                    .piece_map
-                    .contains(&(rel_x, rel_y + ydrop))
-                {
-                    // Draw ghost piece
-                    write!(f, "{}{}", color::Fg(color::Rgb(150,150,150)), piece::BLOCK_STR)?;
-                } else {
-                    write!(f, "{}", self.grid.grid_map[y][x])?;
+    // Whether a Hold press would swap the active piece right now
+    pub fn can_hold(&self) -> bool {
+        self.hold_enabled && !self.hold_used_this_turn
+    }
+
+    // Rows waiting to be removed once the line clear delay runs out
+    pub fn clearing_rows(&self) -> &[usize] {
+        &self.clearing_rows
+    }
+
+    // T pieces follow the 3-corner rule (see `last_action_was_tspin`), with
+    // the two corners the T points towards deciding between mini and full.
+    // Any other piece spins when the last action was a rotation and it can't
+    // move one cell up, down, left or right afterwards.
+    pub fn detect_spin(&self) -> Option<SpinKind> {
+        if self.active_piece.kind == PieceKind::T {
+            return match self.tspin_corners() {
+                _ if !self.last_action_was_tspin() => None,
+                (_, 2) => Some(SpinKind::TSpinFull),
+                _ if self.last_kick.is_some_and(|kick| kick >= UPGRADING_KICK) => {
+                    Some(SpinKind::TSpinFull)
                 }
-            }
-            write!(f, "\r\n")?;
+                _ => Some(SpinKind::TSpinMini),
+            };
+        }
+        if self.last_action_was_rotation && self.is_piece_immobile() {
+            Some(SpinKind::Spin(self.active_piece.kind))
+        } else {
+            None
         }
-        Ok(())
+    }
+
+    // Guideline immobility test: the active piece can't move one cell up,
+    // down, left or right
+    pub fn is_piece_immobile(&self) -> bool {
+        [(0, 1), (0, -1), (-1, 0), (1, 0)]
+            .into_iter()
+            .all(|offset| !self.is_valid_rotation(Rotation::Rot0, offset))
+    }
+
+    // True when the last action was a rotation of a T that left at least
+    // three of the four cells diagonal to its center occupied, with the
+    // walls and floor counting as occupied
+    pub fn last_action_was_tspin(&self) -> bool {
+        self.last_action_was_rotation && self.last_rotation_was_tspin
+    }
+
+    // Occupied corners around the center of the active T, in total and on
+    // the side it points towards. In big mode a corner is a whole 2x2 block,
+    // occupied as soon as any of its cells is.
+    fn tspin_corners(&self) -> (usize, usize) {
+        let scale = self.scale();
+        // The T rotates around its center block, which is (1, 1) in every rotation
+        let (x, y) = (self.active_piece.position.x, self.active_piece.position.y);
+        let facing = match self.active_piece.rotation {
+            Rotation::Rot0 => (0, 1),
+            Rotation::Rot90 => (1, 0),
+            Rotation::Rot180 => (0, -1),
+            Rotation::Rot270 => (-1, 0),
+        };
+        let occupied: Vec<(i32, i32)> = [(-1, -1), (1, -1), (-1, 1), (1, 1)]
+            .into_iter()
+            .filter(|(dx, dy)| {
+                (0..scale * scale).any(|i| {
+                    let cell_x = x + scale * (1 + dx) + i % scale;
+                    let cell_y = y + scale * (1 + dy) + i / scale;
+                    !Grid::<COLS, ROWS>::is_within_bounds(cell_x, cell_y)
+                        || self.grid.get_cell(cell_x, cell_y) != PieceKind::None
+                })
+            })
+            .collect();
+        let front = occupied
+            .iter()
+            .filter(|(dx, dy)| dx * facing.0 + dy * facing.1 > 0)
+            .count();
+        (occupied.len(), front)
+    }
+
+    // Whether the next spawn empties into a new bag, i.e. the active piece
+    // was the last piece of the current one
+    pub fn is_bag_boundary_next(&self) -> bool {
+        self.current_piece_bag.is_empty()
+    }
+
+    fn next_piece_kind(&mut self) -> PieceKind {
+        self.current_piece_bag.pop().unwrap_or_else(|| {
+            let new_bag = piece::gen_piece_bag(&mut self.rng).to_vec();
+            self.current_piece_bag = std::mem::replace(&mut self.next_piece_bag, new_bag);
+            self.current_piece_bag.pop().unwrap()
+        })
+    }
+
+    // Internal state of the randomizer. Together with the two bags this fully
+    // determines the upcoming piece sequence.
+    pub fn rng_state(&self) -> u64 {
+        self.rng.state()
+    }
+
+    pub fn set_rng_state(&mut self, state: u64) {
+        self.rng.set_state(state);
+    }
+
+    pub fn big_mode(&self) -> bool {
+        self.big_mode
+    }
+
+    // Switches big mode on or off and moves the active piece back to its
+    // spawn position for the new scale
+    pub fn set_big_mode(&mut self, big_mode: bool) {
+        self.big_mode = big_mode;
+        let mut piece = self.piece_at_spawn(self.active_piece.kind);
+        piece.piece_id = self.active_piece.piece_id;
+        self.active_piece = piece;
+    }
+
+    // Cells a block of a piece covers along each axis
+    fn scale(&self) -> i32 {
+        if self.big_mode {
+            2
+        } else {
+            1
+        }
+    }
+
+    // Grid cells covered by `piece` at its position, scaled up in big mode
+    fn piece_cells(&self, piece: &Piece) -> Vec<(i32, i32)> {
+        let scale = self.scale();
+        let (x, y) = (piece.position.x, piece.position.y);
+        piece
+            .piece_dimensions
+            .piece_map
+            .iter()
+            .flat_map(|(px, py)| {
+                (0..scale * scale)
+                    .map(move |i| (x + scale * px + i % scale, y + scale * py + i / scale))
+            })
+            .collect()
+    }
+
+    fn spawn_piece(&mut self, kind: PieceKind) -> Piece {
+        let mut piece = self.piece_at_spawn(kind);
+        piece.piece_id = self.pieces_spawned;
+        self.pieces_spawned += 1;
+        piece
+    }
+
+    // A new `kind` piece at its spawn position, without an id yet
+    fn piece_at_spawn(&self, kind: PieceKind) -> Piece {
+        let mut piece = Piece::spawned_in(kind, COLS, Self::VISIBLE_ROWS);
+        if self.big_mode {
+            let map = piece.piece_dimensions.piece_map;
+            let width = piece.piece_dimensions.width;
+            // Centered, on even cells so blocks line up with the 2x2 lattice
+            let left = (COLS as i32 / 2 - width) & !1;
+            let top = if self.spawn_fully_visible {
+                Self::VISIBLE_ROWS
+            } else {
+                ROWS
+            };
+            piece.position.x = left - 2 * PieceDimensions::x_min(map);
+            piece.position.y = top as i32 - 2 * (PieceDimensions::y_max(map) + 1);
+        } else if self.spawn_fully_visible {
+            piece.position.y -= piece.y_max() - (Self::VISIBLE_ROWS as i32 - 1);
+        }
+        piece
+    }
+
+    // Pieces locked per second of play time over the game so far
+    pub fn pieces_per_second(&self) -> f32 {
+        match self.stats.play_time_ms {
+            0 => 0.0,
+            ms => self.stats.pieces_placed as f32 * 1000.0 / ms as f32,
+        }
+    }
+
+    // Attack per piece (APP) over the game so far
+    pub fn attack_per_piece(&self) -> f32 {
+        match self.stats.pieces_placed {
+            0 => 0.0,
+            pieces => self.stats.total_attack_sent as f32 / pieces as f32,
+        }
+    }
+
+    pub fn piece_id(&self) -> u64 {
+        self.active_piece.piece_id
+    }
+
+    pub fn clear_full_rows(&mut self) -> i32 {
+        let full_rows = self.grid.full_rows();
+        let rows_to_clear = full_rows.len() as i32;
+        self.grid.clear_rows(&full_rows);
+        self.last_cleared_row_span = full_rows
+            .first()
+            .zip(full_rows.last())
+            .map(|(low, high)| (*low, *high));
+        if rows_to_clear > 0 {
+            let tspin = matches!(
+                self.last_spin,
+                Some(SpinKind::TSpinMini | SpinKind::TSpinFull)
+            );
+            let difficult = rows_to_clear == 4 || tspin;
+            let base_points = match (self.last_spin, rows_to_clear) {
+                (Some(SpinKind::TSpinFull), 1) => 800,
+                (Some(SpinKind::TSpinFull), 2) => 1200,
+                (Some(SpinKind::TSpinFull), _) => 1600,
+                (Some(SpinKind::TSpinMini), 1) => 200,
+                (Some(SpinKind::TSpinMini), _) => 400,
+                (_, 1) => 100,
+                (_, 2) => 300,
+                (_, 3) => 500,
+                _ => 800,
+            };
+            self.score.points += (base_points as f32
+                * self.score.level as f32
+                * self.clear_multiplier(difficult)) as u32;
+            let levels_before = self.score.lines_cleared / LINES_PER_LEVEL;
+            self.score.lines_cleared += rows_to_clear as u32;
+            self.score.level += self.score.lines_cleared / LINES_PER_LEVEL - levels_before;
+            self.combo += 1;
+            self.back_to_back = difficult;
+            self.last_clear_was_perfect = self
+                .grid
+                .grid_map
+                .iter()
+                .flatten()
+                .all(|kind| *kind == PieceKind::None);
+        } else {
+            // A lock without a clear ends the combo but keeps back-to-back
+            self.combo = -1;
+            self.last_clear_was_perfect = false;
+        }
+        rows_to_clear
+    }
+
+    // Line clearing locks in a row minus one, -1 when there is no combo
+    pub fn combo(&self) -> i32 {
+        self.combo
+    }
+
+    // Lowest and highest row the last line clear removed, None if it
+    // removed nothing
+    pub fn last_cleared_row_span(&self) -> Option<(usize, usize)> {
+        self.last_cleared_row_span
+    }
+
+    pub fn last_clear_was_perfect(&self) -> bool {
+        self.last_clear_was_perfect
+    }
+
+    pub fn back_to_back(&self) -> bool {
+        self.back_to_back
+    }
+
+    // Score multiplier for the next line clear, assuming it continues both
+    // the combo and the back-to-back chain: x1.5 while back-to-back is live,
+    // and +25% for every lock the combo has already lasted.
+    pub fn current_multiplier(&self) -> f32 {
+        self.clear_multiplier(true)
+    }
+
+    fn clear_multiplier(&self, difficult: bool) -> f32 {
+        let back_to_back = if self.back_to_back && difficult {
+            1.5
+        } else {
+            1.0
+        };
+        let combo = 1.0 + 0.25 * (self.combo + 1) as f32;
+        back_to_back * combo
+    }
+
+    pub fn score(&self) -> Score {
+        self.score
+    }
+
+    // Lines still to clear before the level reaches `target`, 0 once it has
+    pub fn lines_remaining_for_level(&self, target: u32) -> u32 {
+        if target <= self.score.level {
+            return 0;
+        }
+        (target - self.score.level) * LINES_PER_LEVEL - self.score.lines_cleared % LINES_PER_LEVEL
+    }
+
+    // Time between gravity steps at the current level, following the
+    // guideline curve (0.8 - (level - 1) * 0.007) ^ (level - 1) seconds
+    pub fn gravity_interval(&self) -> Duration {
+        let level = self.score.level.clamp(1, 100) as f64;
+        let seconds = (0.8 - (level - 1.0) * 0.007).powf(level - 1.0);
+        Duration::from_secs_f64(seconds).max(MIN_GRAVITY_INTERVAL)
+    }
+
+    // Counts down the lock delay of a piece resting on the stack and locks
+    // it once the delay runs out. Meant to be called once per game update.
+    pub fn tick_lock_delay(&mut self) {
+        if self.gameover || self.paused || self.awaiting_spawn || !self.clearing_rows.is_empty() {
+            return;
+        }
+        if self.distance_to_drop() > 0 {
+            self.lock_timer = None;
+            return;
+        }
+        match self.lock_timer.unwrap_or(self.lock_delay_ticks) {
+            0 => {
+                self.lock_timer = None;
+                self.freeze_piece();
+            }
+            ticks => self.lock_timer = Some(ticks - 1),
+        }
+    }
+
+    // Moves the piece down one cell, returning how far it moved
+    fn soft_drop(&mut self) -> u32 {
+        let y = self.active_piece.position.y;
+        self.try_move(Direction::Down);
+        let moved = (y - self.active_piece.position.y) as u32;
+        if self.distance_to_drop() == 0 {
+            match self.soft_drop_lock {
+                SoftDropLock::Instant => {
+                    self.freeze_piece();
+                }
+                SoftDropLock::Delayed => {
+                    self.lock_timer.get_or_insert(self.lock_delay_ticks);
+                }
+            }
+        }
+        moved
+    }
+
+    // Checked after every rotation, kicked or not. Rotating a T into a slot
+    // without a kick counts as a T-spin in the guideline too, so this doesn't
+    // wait for a wall kick.
+    fn is_tspin_position(&self) -> bool {
+        self.active_piece.kind == PieceKind::T && self.tspin_corners().0 >= 3
+    }
+
+    // Moving or rotating a resting piece restarts its lock delay, up to
+    // `MAX_LOCK_RESETS` times per piece
+    fn restart_lock_delay(&mut self) {
+        if self.lock_timer.is_some() && self.lock_resets < MAX_LOCK_RESETS {
+            self.lock_timer = Some(self.lock_delay_ticks);
+            self.lock_resets += 1;
+        }
+    }
+
+    pub fn gravity_accumulator_ms(&self) -> u32 {
+        self.gravity_accumulator_ms
+    }
+
+    pub fn set_gravity_accumulator_ms(&mut self, ms: u32) {
+        self.gravity_accumulator_ms = ms;
+    }
+
+    // Adds elapsed time to the gravity accumulator and applies gravity once
+    // for every full gravity interval it now holds. A held soft drop steps
+    // the piece down at `soft_drop_repeat_ms` on top of that.
+    pub fn advance_gravity(&mut self, elapsed_ms: u32) {
+        if self.paused || self.awaiting_spawn {
+            return;
+        }
+        let interval_ms = self.gravity_interval().as_millis() as u32;
+        self.stats.play_time_ms += elapsed_ms as u64;
+        self.gravity_accumulator_ms += elapsed_ms;
+        while self.gravity_accumulator_ms >= interval_ms && !self.gameover {
+            self.gravity_accumulator_ms -= interval_ms;
+            self.apply_gravity();
+        }
+        if let (true, Some(repeat_ms)) = (self.soft_drop_held, self.soft_drop_repeat_ms) {
+            let repeat_ms = repeat_ms.max(1);
+            self.soft_drop_accumulator_ms += elapsed_ms;
+            while self.soft_drop_accumulator_ms >= repeat_ms
+                && !self.gameover
+                && self.clearing_rows.is_empty()
+            {
+                self.soft_drop_accumulator_ms -= repeat_ms;
+                self.score.points += self.soft_drop();
+            }
+        }
+    }
+
+    // Advances the game by `dt` without any terminal or clock: gravity for
+    // the elapsed time, one tick of the lock delay and the regular update.
+    // Meant to be called at a fixed timestep by headless frontends.
+    pub fn step(&mut self, dt: Duration) -> StepOutcome {
+        if self.gameover {
+            return StepOutcome::GameOver;
+        }
+        let (pieces_placed, lines_cleared) = (self.stats.pieces_placed, self.score.lines_cleared);
+        let elapsed = self.step_remainder + dt;
+        let elapsed_ms = elapsed.as_millis() as u32;
+        self.step_remainder = elapsed - Duration::from_millis(elapsed_ms as u64);
+        self.advance_gravity(elapsed_ms);
+        self.tick_lock_delay();
+        self.on_update(dt);
+        if self.gameover {
+            StepOutcome::GameOver
+        } else if self.score.lines_cleared > lines_cleared {
+            StepOutcome::LineCleared(self.score.lines_cleared - lines_cleared)
+        } else if self.stats.pieces_placed > pieces_placed {
+            StepOutcome::Locked
+        } else {
+            StepOutcome::Stepped
+        }
+    }
+
+    // Repeatedly clears full rows, letting every block fall as far as it can
+    // in its column after each clear. Returns the lines cleared per step.
+    pub fn cascade_clear(&mut self) -> Vec<u8> {
+        let mut steps = Vec::new();
+        while !self.grid.full_rows().is_empty() {
+            let cleared = self.clear_full_rows();
+            steps.push(cleared as u8);
+            self.grid.apply_cascade_gravity();
+        }
+        steps
+    }
+
+    pub fn queue_garbage(&mut self, lines: usize, hole_column: usize) {
+        self.pending_garbage.push((lines, hole_column));
+    }
+
+    // Queues one line of garbage per entry of `garbage_pattern(seed, lines)`,
+    // with the holes spread over the width of this grid. The first line ends
+    // up at the top of the garbage.
+    pub fn queue_garbage_pattern(&mut self, seed: u64, lines: usize) {
+        garbage_holes(seed, lines, COLS)
+            .into_iter()
+            .for_each(|hole_column| self.queue_garbage(1, hole_column));
+    }
+
+    // Inserts all pending garbage into the grid, topping out on overflow
+    pub fn apply_pending_garbage(&mut self) {
+        for (lines, hole_column) in std::mem::take(&mut self.pending_garbage) {
+            self.add_garbage(lines, hole_column);
+        }
+    }
+
+    // Pushes the stack up by `lines` garbage rows open at `hole_column`
+    // right away. The active piece is lifted with the stack if it would
+    // overlap it. Tops out when blocks are pushed off the grid or the
+    // active piece can't be lifted clear.
+    pub fn add_garbage(&mut self, lines: usize, hole_column: usize) {
+        if self.grid.add_garbage(lines, hole_column) {
+            self.end_game(GameOverReason::TopOut);
+            return;
+        }
+        let mut lifted = self.active_piece.clone();
+        for _ in 0..lines {
+            if self.fits(&lifted) {
+                break;
+            }
+            lifted.position.y += 1;
+        }
+        if self.fits(&lifted) {
+            self.active_piece = lifted;
+        } else {
+            self.end_game(GameOverReason::TopOut);
+        }
+    }
+
+    // The grid as it would look once the pending garbage is inserted
+    pub fn preview_with_garbage(&self) -> Grid<COLS, ROWS> {
+        let mut grid = self.grid.clone();
+        self.pending_garbage
+            .iter()
+            .for_each(|(lines, hole_column)| {
+                grid.add_garbage(*lines, *hole_column);
+            });
+        grid
+    }
+
+    // Locks `piece` into a copy of the board and clears any full rows.
+    // Returns the resulting grid and the number of lines cleared.
+    pub fn simulate_lock(&self, piece: &Piece) -> (Grid<COLS, ROWS>, i32) {
+        let mut gs = self.clone();
+        gs.piece_cells(piece)
+            .into_iter()
+            .for_each(|(x, y)| gs.grid.set_cell(x, y, piece.kind));
+        let lines = gs.clear_full_rows();
+        (gs.grid, lines)
+    }
+
+    // Every resting position of the active piece, over all rotations and
+    // columns, found by dropping it straight down from the top of the grid.
+    // Whether the piece could actually get there is not checked.
+    pub fn all_landings(&self) -> Vec<Piece> {
+        let mut landings: Vec<Piece> = Vec::new();
+        // Big mode pieces stay on the 2x2 lattice of the active piece
+        let scale = self.scale();
+        let columns = (-2 * COLS as i32..COLS as i32)
+            .filter(|x| (x - self.active_piece.position.x).rem_euclid(scale) == 0);
+        for rotation in 0..4 {
+            let mut piece = self.active_piece.clone();
+            piece.rotate(Rotation::from(rotation));
+            for x in columns.clone() {
+                piece.position.x = x;
+                let Some(landed) = self.drop_from_top(piece.clone()) else {
+                    continue;
+                };
+                let cells = |p: &Piece| {
+                    let mut cells = self.piece_cells(p);
+                    cells.sort();
+                    cells
+                };
+                if !landings.iter().any(|other| cells(other) == cells(&landed)) {
+                    landings.push(landed);
+                }
+            }
+        }
+        landings
+    }
+
+    // Landing of a `kind` piece leaving the board with the lowest
+    // `cost_function`, whatever the active piece is
+    pub fn best_fit(&self, kind: PieceKind) -> Placement {
+        let mut gs = self.clone();
+        gs.active_piece = gs.piece_at_spawn(kind);
+        let best = gs
+            .all_landings()
+            .into_iter()
+            .map(|piece| (cost_function(&gs.simulate_lock(&piece).0), piece))
+            .min_by(|(a, _), (b, _)| a.total_cmp(b))
+            .map_or(gs.active_piece, |(_, piece)| piece);
+        Placement {
+            kind,
+            rotation: best.rotation,
+            x: best.position.x,
+        }
+    }
+
+    // Deepest row the bottom of the active piece can reach with its left edge
+    // in each column, by moving left, right and down from where it is now
+    // without rotating. Slides under overhangs count, columns the piece
+    // can't get to are -1.
+    pub fn reachable_depths(&self) -> Vec<i32> {
+        let mut depths = vec![-1; COLS];
+        let scale = self.scale();
+        let start = self.active_piece.position;
+        let mut visited = HashSet::from([(start.x, start.y)]);
+        let mut queue = vec![(start.x, start.y)];
+        let mut piece = self.active_piece.clone();
+        while let Some((x, y)) = queue.pop() {
+            piece.position = GridPosition { x, y };
+            if !self.fits(&piece) {
+                continue;
+            }
+            let cells = self.piece_cells(&piece);
+            let column = cells.iter().map(|(x, _)| *x).min().unwrap() as usize;
+            let bottom = cells.iter().map(|(_, y)| *y).min().unwrap();
+            if depths[column] == -1 || bottom < depths[column] {
+                depths[column] = bottom;
+            }
+            for next in [(x - scale, y), (x + scale, y), (x, y - scale)] {
+                if visited.insert(next) {
+                    queue.push(next);
+                }
+            }
+        }
+        depths
+    }
+
+    // Moves `piece` to the top of the grid in its column and lets it fall
+    // until it rests. None if it doesn't fit there at all.
+    fn drop_from_top(&self, mut piece: Piece) -> Option<Piece> {
+        let scale = self.scale();
+        let top = PieceDimensions::y_max(piece.piece_dimensions.piece_map);
+        piece.position.y = ROWS as i32 - scale * (top + 1);
+        if !self.fits(&piece) {
+            return None;
+        }
+        while {
+            piece.position.y -= scale;
+            self.fits(&piece)
+        } {}
+        piece.position.y += scale;
+        Some(piece)
+    }
+
+    // Total attack sent by locking the placements one after another, with
+    // back-to-back and combo carried over between them. Stops early at the
+    // first placement that doesn't fit.
+    pub fn planned_attack(&self, placements: &[Placement]) -> u32 {
+        let mut gs = self.clone();
+        gs.line_clear_delay = Duration::ZERO;
+        let mut attack = 0;
+        for placement in placements {
+            if gs.gameover {
+                break;
+            }
+            let Some(result) = gs.lock_placement(placement) else {
+                break;
+            };
+            attack += result.attack;
+        }
+        attack
+    }
+
+    // Drops a piece from the top of the grid as described by `placement`
+    // and locks it in place of the active piece. None if it doesn't fit.
+    fn lock_placement(&mut self, placement: &Placement) -> Option<LockResult> {
+        let mut piece = Piece::new(placement.kind);
+        piece.rotate(placement.rotation);
+        piece.position.x = placement.x;
+        piece.piece_id = self.active_piece.piece_id;
+        self.active_piece = self.drop_from_top(piece)?;
+        self.last_action_was_rotation = false;
+        Some(self.freeze_piece())
+    }
+
+    // Moves the active piece straight to `placement` and locks it there,
+    // without going through the buttons that would get it there. Panics if
+    // the placement is for another kind of piece or doesn't fit the grid.
+    pub fn apply_placement(&mut self, placement: &Placement) -> LockResult {
+        assert_eq!(
+            placement.kind, self.active_piece.kind,
+            "Placement is for another piece"
+        );
+        self.lock_placement(placement)
+            .unwrap_or_else(|| panic!("Placement doesn't fit: {:?}", placement))
+    }
+
+    // How soon sampled piece sequences top out after locking `placement`,
+    // each following piece going to its `best_fit`. A sample scores 1.0 if
+    // the placement itself tops out, less the more of the `lookahead` pieces
+    // after it lock first, and 0.0 if they all do. The preview is played as
+    // is; only the pieces beyond it differ between samples.
+    pub fn topout_risk(&self, placement: &Placement, lookahead: usize) -> f32 {
+        let pieces = lookahead as f32 + 1.0;
+        let mut risk = 0.0;
+        for sample in 0..TOPOUT_RISK_SAMPLES {
+            let mut gs = self.clone();
+            gs.line_clear_delay = Duration::ZERO;
+            gs.set_rng_state(self.rng_state() ^ sample.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+            let mut placement = *placement;
+            for locked in 0..=lookahead {
+                if gs.lock_placement(&placement).is_none() || gs.gameover {
+                    risk += (pieces - locked as f32) / pieces;
+                    break;
+                }
+                placement = gs.best_fit(gs.active_piece.kind);
+            }
+        }
+        risk / TOPOUT_RISK_SAMPLES as f32
+    }
+
+    // Most lines any landing of the active piece would clear
+    pub fn best_single_move_clear(&self) -> u8 {
+        self.all_landings()
+            .iter()
+            .map(|piece| self.simulate_lock(piece).1 as u8)
+            .max()
+            .unwrap_or(0)
+    }
+
+    // Goes column by column through the spans of `piece`, scaled up in big
+    // mode. A column sticking out of the grid fails before any cell is read.
+    fn fits(&self, piece: &Piece) -> bool {
+        let scale = self.scale();
+        let (x, y) = (piece.position.x, piece.position.y);
+        let x_min = PieceDimensions::x_min(piece.piece_dimensions.piece_map);
+        piece
+            .column_spans()
+            .iter()
+            .enumerate()
+            .all(|(i, (low, high))| {
+                let (bottom, top) = (y + scale * low, y + scale * high + scale - 1);
+                (0..scale).all(|s| {
+                    let col = x + scale * (x_min + i as i32) + s;
+                    Grid::<COLS, ROWS>::is_rect_inside(col, col, bottom, top)
+                        && (bottom..=top).all(|row| self.grid.get_cell(col, row) == PieceKind::None)
+                })
+            })
+    }
+
+    // Surface smoothness once `piece` is locked and rows are cleared, as
+    // 1 / (1 + bumpiness). A perfectly flat surface scores 1.
+    pub fn flatness_after(&self, piece: &Piece) -> f32 {
+        1.0 / (1.0 + self.simulate_lock(piece).0.bumpiness() as f32)
+    }
+
+    // Per-column height change caused by locking `piece`
+    pub fn height_delta(&self, piece: &Piece) -> [i32; COLS] {
+        let before = self.grid.heights(ROWS as i32);
+        let after = self.simulate_lock(piece).0.heights(ROWS as i32);
+        let mut delta = [0i32; COLS];
+        (0..COLS).for_each(|col| delta[col] = after[col] - before[col]);
+        delta
+    }
+
+    pub fn delta_from(&self, previous: &Self) -> GameStateDelta {
+        let mut cells = Vec::new();
+        for (y, (row, old_row)) in self
+            .grid
+            .grid_map
+            .iter()
+            .zip(previous.grid.grid_map.iter())
+            .enumerate()
+        {
+            for (x, (kind, old_kind)) in row.iter().zip(old_row.iter()).enumerate() {
+                if kind != old_kind {
+                    cells.push((x, y, *kind));
+                }
+            }
+        }
+        GameStateDelta {
+            cells,
+            active_piece: self.active_piece.clone(),
+            gameover: self.gameover,
+            current_piece_bag: self.current_piece_bag.clone(),
+            next_piece_bag: self.next_piece_bag.clone(),
+            rng_state: self.rng_state(),
+            score: self.score,
+        }
+    }
+
+    pub fn apply_delta(&mut self, delta: &GameStateDelta) {
+        delta
+            .cells
+            .iter()
+            .for_each(|(x, y, kind)| self.grid.grid_map[*y][*x] = *kind);
+        self.active_piece = delta.active_piece.clone();
+        self.gameover = delta.gameover;
+        self.current_piece_bag = delta.current_piece_bag.clone();
+        self.next_piece_bag = delta.next_piece_bag.clone();
+        self.set_rng_state(delta.rng_state);
+        self.score = delta.score;
+    }
+
+    // Rows that still need filling, ignoring the well (the lowest column),
+    // before a vertical I dropped into the well clears four lines. None if
+    // the well is too close to the top of the visible field.
+    pub fn lines_until_tetris(&self) -> Option<usize> {
+        let heights = self.grid.heights(ROWS as i32);
+        let (well, well_height) = heights
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, height)| **height)
+            .map(|(col, height)| (col, *height as usize))
+            .unwrap();
+        if well_height + 4 > Self::VISIBLE_ROWS {
+            return None;
+        }
+        let missing = (well_height..well_height + 4)
+            .filter(|row| {
+                (0..COLS)
+                    .filter(|col| *col != well)
+                    .any(|col| self.grid.grid_map[*row][col] == PieceKind::None)
+            })
+            .count();
+        Some(missing)
+    }
+
+    pub fn distance_to_drop(&self) -> i32 {
+        if self.big_mode {
+            let mut piece = self.active_piece.clone();
+            let mut distance = 0;
+            while {
+                piece.position.y -= 2;
+                self.fits(&piece)
+            } {
+                distance += 2;
+            }
+            return distance;
+        }
+        let (x, y) = (self.active_piece.position.x, self.active_piece.position.y);
+        let xmin = PieceDimensions::x_min(self.active_piece.piece_dimensions.piece_map);
+        (0..self.active_piece.piece_dimensions.width)
+            .filter(|w| 0 <= (x + w + xmin) && (x + w + xmin) < COLS as i32)
+            .map(|w| {
+                self.active_piece.piece_dimensions.skirt[w as usize] + y
+                    - self
+                        .grid
+                        .heights(self.active_piece.piece_dimensions.skirt[w as usize] + y)
+                        [(x + w + xmin) as usize]
+            })
+            .min()
+            // Only reachable with the piece entirely beside the grid
+            .unwrap_or_else(|| self.active_piece.y_min().max(0))
+    }
+
+    // Columns occupied by the active piece, left to right
+    pub fn landing_columns(&self) -> Vec<i32> {
+        let mut columns: Vec<i32> = self
+            .piece_cells(&self.active_piece)
+            .into_iter()
+            .map(|(x, _)| x)
+            .collect();
+        columns.sort();
+        columns.dedup();
+        columns
+    }
+
+    // Columns the active piece can cover by sliding sideways at its current
+    // height and rotation. Anything sticking out of the stack at that height
+    // cuts off the columns behind it.
+    pub fn column_access(&self) -> [bool; COLS] {
+        let mut access = [false; COLS];
+        if self.awaiting_spawn {
+            return access;
+        }
+        for dx in [-self.scale(), self.scale()] {
+            let mut piece = self.active_piece.clone();
+            while self.fits(&piece) {
+                self.piece_cells(&piece)
+                    .into_iter()
+                    .for_each(|(x, _)| access[x as usize] = true);
+                piece.position.x += dx;
+            }
+        }
+        access
+    }
+
+    // Where the active piece would land if it was hard dropped now
+    pub fn ghost_position(&self) -> GridPosition {
+        let mut position = self.active_piece.position;
+        position.y -= self.distance_to_drop();
+        position
+    }
+
+    // Cells to draw as the ghost for the current `ghost_style`
+    pub fn ghost_cells(&self) -> Vec<(i32, i32)> {
+        if self.awaiting_spawn || !self.show_ghost {
+            return Vec::new();
+        }
+        let mut ghost = self.active_piece.clone();
+        ghost.position = self.ghost_position();
+        let cells = self.piece_cells(&ghost).into_iter();
+        match self.ghost_style {
+            GhostStyle::Full => cells.collect(),
+            GhostStyle::ColumnMarker => self
+                .landing_columns()
+                .into_iter()
+                .map(|col| {
+                    cells
+                        .clone()
+                        .filter(|(x, _)| *x == col)
+                        .min_by(|(_, y1), (_, y2)| y1.cmp(y2))
+                        .unwrap()
+                })
+                .collect(),
+            GhostStyle::Off => Vec::new(),
+        }
+    }
+
+    // Moves the active piece onto the stack without locking it.
+    // Returns the number of cells it moved.
+    pub fn drop_to_floor(&mut self) -> i32 {
+        let distance = self.distance_to_drop();
+        if distance > 0 {
+            self.active_piece.position.y -= distance;
+            self.last_action_was_rotation = false;
+        }
+        distance
+    }
+
+    // Hard drops the active piece, scoring two points per cell, and locks it
+    pub fn drop_piece(&mut self) {
+        let from = self.active_piece.position;
+        self.score.points += 2 * self.drop_to_floor() as u32;
+        self.last_hard_drop = Some((from, self.active_piece.position));
+        self.freeze_piece();
+    }
+
+    // Where the most recent hard drop started and ended, for drawing a
+    // trail. Cleared by the next input.
+    pub fn last_hard_drop(&self) -> Option<(GridPosition, GridPosition)> {
+        self.last_hard_drop
+    }
+
+    // Runs the timers that aren't tied to gravity, `elapsed` being the game
+    // time since the previous update
+    pub fn on_update(&mut self, elapsed: Duration) {
+        if self.paused {
+            return;
+        }
+        if let Some(remaining) = self.line_clear_remaining {
+            self.line_clear_remaining = Some(remaining.saturating_sub(elapsed));
+        }
+        if self
+            .line_clear_remaining
+            .is_some_and(|remaining| remaining.is_zero())
+        {
+            self.clearing_rows.clear();
+            self.clear_full_rows();
+            self.line_clear_remaining = None;
+            self.spawn_next_piece();
+        }
+        self.lock_flash_remaining = self.lock_flash_remaining.saturating_sub(elapsed);
+    }
+
+    // Time left of the flash shown after the most recent lock
+    pub fn lock_flash_remaining(&self) -> Duration {
+        self.lock_flash_remaining
+    }
+
+    fn is_valid_move(&self, dir: Direction) -> bool {
+        let (dx, dy): (i32, i32) = match dir {
+            Direction::Left => (-1, 0),
+            Direction::Right => (1, 0),
+            Direction::Up => (0, 1),
+            Direction::Down => (0, -1),
+        };
+        let mut moved = self.active_piece.clone();
+        moved.position.x += dx * self.scale();
+        moved.position.y += dy * self.scale();
+        self.fits(&moved)
+    }
+
+    // Moves the active piece one block, which is two cells in big mode
+    fn shift_active_piece(&mut self, dir: Direction) {
+        (0..self.scale()).for_each(|_| self.active_piece.move_piece(dir));
+    }
+
+    fn try_move(&mut self, dir: Direction) {
+        if self.is_valid_move(dir) {
+            self.shift_active_piece(dir);
+            self.last_action_was_rotation = false;
+            self.restart_lock_delay();
+        }
+    }
+
+    fn is_valid_rotation(&self, rot: Rotation, offset: (i32, i32)) -> bool {
+        let mut rotated = self.active_piece.clone();
+        rotated.rotate(rot);
+        rotated.position.x += offset.0 * self.scale();
+        rotated.position.y += offset.1 * self.scale();
+        self.fits(&rotated)
+    }
+
+    // Whether the most recent successful rotation needed a wall kick
+    pub fn last_rotation_kicked(&self) -> bool {
+        self.last_kick.is_some()
+    }
+
+    fn try_rotate(&mut self, rot: Rotation) {
+        let transition = (
+            self.active_piece.rotation,
+            (self.active_piece.rotation + rot),
+        );
+
+        let offset_list: &[(i32, i32)] = match self.active_piece.kind {
+            PieceKind::I => match transition {
+                (Rotation::Rot0, Rotation::Rot90) => &[(-2, 0), (1, 0), (-2, -1), (1, 2)],
+                (Rotation::Rot90, Rotation::Rot0) => &[(2, 0), (-1, 0), (2, 1), (-1, -2)],
+                (Rotation::Rot90, Rotation::Rot180) => &[(-1, 0), (2, 0), (-1, 2), (2, -1)],
+                (Rotation::Rot180, Rotation::Rot90) => &[(1, 0), (-2, 0), (1, -2), (-2, 1)],
+                (Rotation::Rot180, Rotation::Rot270) => &[(2, 0), (-1, 0), (2, 1), (-1, -2)],
+                (Rotation::Rot270, Rotation::Rot180) => &[(-2, 0), (1, 0), (-2, -1), (1, 2)],
+                (Rotation::Rot270, Rotation::Rot0) => &[(1, 0), (-2, 0), (1, -2), (-2, 1)],
+                (Rotation::Rot0, Rotation::Rot270) => &[(-1, 0), (2, 0), (-1, 2), (2, -1)],
+                (Rotation::Rot0, Rotation::Rot180) => &[(-1, 0), (-2, 0), (1, 0), (2, 0), (0, 1)],
+                (Rotation::Rot90, Rotation::Rot270) => &[(0, 1), (0, 2), (0, -1), (0, -2), (-1, 0)],
+                (Rotation::Rot180, Rotation::Rot0) => &[(1, 0), (2, 0), (-1, 0), (-2, 0), (0, -1)],
+                (Rotation::Rot270, Rotation::Rot90) => &[(0, 1), (0, 2), (0, -1), (0, -2), (1, 0)],
+                _ => unreachable!(),
+            },
+            _ => match transition {
+                (Rotation::Rot0, Rotation::Rot90) => &[(-1, 0), (-1, 1), (0, -2), (-1, -2)],
+                (Rotation::Rot90, Rotation::Rot0) => &[(1, 0), (1, -1), (0, 2), (1, 2)],
+                (Rotation::Rot90, Rotation::Rot180) => &[(1, 0), (1, -1), (0, 2), (1, 2)],
+                (Rotation::Rot180, Rotation::Rot90) => &[(-1, 0), (-1, 1), (0, -2), (-1, -2)],
+                (Rotation::Rot180, Rotation::Rot270) => &[(1, 0), (1, 1), (0, -2), (1, -2)],
+                (Rotation::Rot270, Rotation::Rot180) => &[(-1, 0), (-1, -1), (0, 2), (-1, 2)],
+                (Rotation::Rot270, Rotation::Rot0) => &[(-1, 0), (-1, -1), (0, 2), (-1, 2)],
+                (Rotation::Rot0, Rotation::Rot270) => &[(1, 0), (1, 1), (0, -2), (1, -2)],
+                // 180 degree kicks, as used by TETR.IO
+                (Rotation::Rot0, Rotation::Rot180) => &[(0, 1), (1, 1), (-1, 1), (1, 0), (-1, 0)],
+                (Rotation::Rot90, Rotation::Rot270) => &[(1, 0), (1, 2), (1, 1), (0, 2), (0, 1)],
+                (Rotation::Rot180, Rotation::Rot0) => {
+                    &[(0, -1), (-1, -1), (1, -1), (-1, 0), (1, 0)]
+                }
+                (Rotation::Rot270, Rotation::Rot90) => &[(-1, 0), (-1, 2), (-1, 1), (0, 2), (0, 1)],
+                _ => unreachable!(),
+            },
+        };
+        if self.is_valid_rotation(rot, (0, 0)) {
+            self.active_piece.rotate(rot);
+            self.last_action_was_rotation = true;
+            self.last_kick = None;
+            self.last_rotation_was_tspin = self.is_tspin_position();
+            self.restart_lock_delay();
+        } else {
+            for (kick, offset) in offset_list.iter().copied().enumerate() {
+                if self.is_valid_rotation(rot, offset) {
+                    self.active_piece.position.x += offset.0 * self.scale();
+                    self.active_piece.position.y += offset.1 * self.scale();
+                    self.active_piece.rotate(rot);
+                    self.last_action_was_rotation = true;
+                    self.last_kick = Some(kick);
+                    self.last_rotation_was_tspin = self.is_tspin_position();
+                    self.restart_lock_delay();
+                    break;
+                }
+            }
+        };
+    }
+
+    // Handles `button` like `on_button_pressed` and reports what it caused,
+    // in the order the events happened
+    pub fn apply_button_logged(&mut self, button: Button) -> Vec<GameEvent> {
+        let before = self.clone();
+        self.on_button_pressed(button);
+        self.events_since(&before)
+    }
+
+    // Runs `on_update` and reports what it caused. With a line clear delay
+    // this is where the delayed rows are cleared and the next piece spawns.
+    pub fn on_update_logged(&mut self, elapsed: Duration) -> Vec<GameEvent> {
+        let before = self.clone();
+        self.on_update(elapsed);
+        self.events_since(&before)
+    }
+
+    fn events_since(&self, before: &Self) -> Vec<GameEvent> {
+        let mut events = Vec::new();
+        let piece_changed = self.active_piece.piece_id != before.active_piece.piece_id;
+        let moved = self.active_piece.position.x != before.active_piece.position.x
+            || self.active_piece.position.y != before.active_piece.position.y
+            || self.active_piece.rotation as i32 != before.active_piece.rotation as i32;
+        let locked = self.stats.pieces_placed > before.stats.pieces_placed;
+        // A piece waiting out a line clear delay keeps its id after locking
+        if !piece_changed && !locked && moved {
+            events.push(GameEvent::PieceMoved);
+        }
+        if locked {
+            events.push(GameEvent::PieceLocked(before.active_piece.kind));
+        }
+        if self.score.lines_cleared > before.score.lines_cleared {
+            let lines = self.score.lines_cleared - before.score.lines_cleared;
+            events.push(GameEvent::LinesCleared(lines));
+        }
+        if self.hold_used_this_turn && !before.hold_used_this_turn {
+            events.push(GameEvent::PieceHeld(before.active_piece.kind));
+        }
+        if piece_changed && !self.gameover {
+            events.push(GameEvent::PieceSpawned(self.active_piece.kind));
+        }
+        if self.gameover && !before.gameover {
+            events.push(GameEvent::GameOver);
+        }
+        events
+    }
+
+    pub fn on_button_pressed(&mut self, button: Button) {
+        if self.gameover {
+            if let Button::Restart = button {
+                self.reset();
+            }
+            return;
+        }
+        if self.paused && !matches!(button, Button::Pause | Button::Quit) {
+            return;
+        }
+        let blocked = self.awaiting_spawn || !self.clearing_rows.is_empty();
+        if blocked && !matches!(button, Button::Quit | Button::Pause) {
+            return;
+        }
+        self.last_hard_drop = None;
+        let button = match (self.mirror_controls, button) {
+            (true, Button::MoveLeft) => Button::MoveRight,
+            (true, Button::MoveRight) => Button::MoveLeft,
+            (true, Button::RotateClockwise) => Button::RotateCounterClockwise,
+            (true, Button::RotateCounterClockwise) => Button::RotateClockwise,
+            _ => button,
+        };
+        match button {
+            Button::Quit => self.gameover = true,
+            Button::MoveDown => {
+                self.soft_drop();
+            }
+            Button::SoftDrop => {
+                self.score.points += self.soft_drop();
+                self.soft_drop_held = self.soft_drop_repeat_ms.is_some();
+                self.soft_drop_accumulator_ms = 0;
+            }
+            Button::MoveLeft => self.try_move(Direction::Left),
+            Button::MoveRight => self.try_move(Direction::Right),
+            Button::Drop => self.drop_piece(),
+            Button::RotateClockwise => self.try_rotate(Rotation::Rot90),
+            Button::RotateCounterClockwise => self.try_rotate(Rotation::Rot270),
+            Button::Rotate180 => self.try_rotate(Rotation::Rot180),
+            Button::Hold => self.hold(),
+            Button::Pause => self.paused = !self.paused,
+            Button::Restart => (),
+        };
+    }
+
+    // Only matters for buttons that act while held, currently SoftDrop
+    pub fn on_button_released(&mut self, button: Button) {
+        if let Button::SoftDrop = button {
+            self.soft_drop_held = false;
+        }
+    }
+}
+
+impl<const COLS: usize, const ROWS: usize> fmt::Display for GameState<COLS, ROWS> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.render_with_theme(&RenderTheme::default()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cascade_clear_counts_each_step() {
+        let mut gs = GameState::default();
+        // Row 0 is missing its last block, row 1 is full and row 2 holds a
+        // single block that will cascade into the gap once row 1 clears.
+        (0..GRID_COLUMNS as i32 - 1).for_each(|x| gs.grid.set_cell(x, 0, PieceKind::I));
+        (0..GRID_COLUMNS as i32).for_each(|x| gs.grid.set_cell(x, 1, PieceKind::J));
+        gs.grid.set_cell(GRID_COLUMNS as i32 - 1, 2, PieceKind::L);
+
+        assert_eq!(gs.cascade_clear(), vec![1, 1]);
+        assert_eq!(gs.grid.widths(), [0i32; GRID_ROWS]);
+    }
+
+    #[test]
+    fn lock_flash_counts_down() {
+        let mut gs = GameState {
+            lock_flash_duration: Duration::from_millis(200),
+            ..Default::default()
+        };
+        assert_eq!(gs.lock_flash_remaining(), Duration::ZERO);
+
+        gs.drop_piece();
+        assert_eq!(gs.lock_flash_remaining(), gs.lock_flash_duration);
+
+        gs.on_update(Duration::from_millis(10));
+        assert_eq!(gs.lock_flash_remaining(), Duration::from_millis(190));
+
+        // Paused time doesn't count
+        gs.on_button_pressed(Button::Pause);
+        gs.step(Duration::from_millis(100));
+        assert_eq!(gs.lock_flash_remaining(), Duration::from_millis(190));
+        gs.on_button_pressed(Button::Pause);
+
+        gs.step(Duration::from_millis(100));
+        assert_eq!(gs.lock_flash_remaining(), Duration::from_millis(90));
+        gs.on_update(Duration::from_millis(200));
+        assert_eq!(gs.lock_flash_remaining(), Duration::ZERO);
+    }
+
+    #[test]
+    fn spawned_pieces_get_increasing_ids() {
+        let mut gs = GameState::default();
+        let mut last_id = gs.piece_id();
+        for _ in 0..3 {
+            gs.drop_piece();
+            gs.on_update(Duration::ZERO);
+            assert!(gs.piece_id() > last_id);
+            last_id = gs.piece_id();
+        }
+    }
+
+    #[test]
+    fn restoring_rng_state_replays_pieces() {
+        let mut gs = GameState::default();
+        (0..10).for_each(|_| {
+            gs.next_piece_kind();
+        });
+        let state = gs.rng_state();
+        let bags = (gs.current_piece_bag.clone(), gs.next_piece_bag.clone());
+
+        let first_run: Vec<_> = (0..21).map(|_| gs.next_piece_kind()).collect();
+        gs.set_rng_state(state);
+        (gs.current_piece_bag, gs.next_piece_bag) = bags;
+        let second_run: Vec<_> = (0..21).map(|_| gs.next_piece_kind()).collect();
+        assert_eq!(first_run, second_run);
+    }
+
+    #[test]
+    fn height_delta_for_flat_placement() {
+        let gs = GameState::default();
+        let mut piece = Piece::new(PieceKind::O);
+        piece.position = piece::GridPosition { x: 4, y: 0 };
+
+        let mut expected = [0i32; GRID_COLUMNS];
+        expected[4] = 2;
+        expected[5] = 2;
+        assert_eq!(gs.height_delta(&piece), expected);
+    }
+
+    #[test]
+    fn t_spin_triple_is_detected() {
+        // The T slides under the overhang at (2, 4) and kicks down into the slot
+        let board = [
+            "..G.......",
+            "..........",
+            "GG.GGGGGGG",
+            "G..GGGGGGG",
+            "GG.GGGGGGG",
+        ];
+        let mut gs = GameState::from_scenario(&board, &[PieceKind::O], PieceKind::T);
+        gs.active_piece.position = piece::GridPosition { x: 0, y: 2 };
+        gs.on_button_pressed(Button::RotateCounterClockwise);
+        assert!(gs.last_rotation_kicked());
+        assert!(gs.last_action_was_tspin());
+        assert_eq!(gs.detect_spin(), Some(SpinKind::TSpinFull));
+
+        let result = gs.freeze_piece();
+        assert_eq!(result.spin, Some(SpinKind::TSpinFull));
+        assert_eq!(result.lines_cleared, 3);
+        assert_eq!(result.attack, 6);
+        assert_eq!(gs.score().points, 1600);
+    }
+
+    #[test]
+    fn builder_applies_settings() {
+        let gs = GameStateBuilder::new().seed(7).enable_ghost(false).build();
+        assert!(!gs.show_ghost);
+        assert!(gs.ghost_cells().is_empty());
+        let same_seed = GameState::with_seed(7);
+        assert_eq!(gs.active_piece.kind, same_seed.active_piece.kind);
+        assert_eq!(gs.current_piece_bag, same_seed.current_piece_bag);
+        assert_eq!(gs.next_piece_bag, same_seed.next_piece_bag);
+
+        let mut gs = GameStateBuilder::new()
+            .gravity_level(5)
+            .enable_hold(false)
+            .build();
+        assert_eq!(gs.score().level, 5);
+        let kind = gs.active_piece.kind;
+        gs.on_button_pressed(Button::Hold);
+        assert_eq!(gs.active_piece.kind, kind);
+        assert_eq!(gs.hold_piece, None);
+
+        let wide = GameStateBuilder::new()
+            .seed(7)
+            .grid_size::<16, 30>()
+            .enable_hold(false)
+            .build();
+        assert_eq!(wide.grid.grid_map.len(), 30);
+        assert_eq!(wide.grid.grid_map[0].len(), 16);
+        assert!(!wide.hold_enabled);
+        assert_eq!(wide.active_piece.kind, same_seed.active_piece.kind);
+        assert_eq!(wide.active_piece.y_max(), 26 + 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "too small")]
+    fn builder_rejects_tiny_grid() {
+        GameStateBuilder::new().grid_size::<3, 24>();
+    }
+
+    #[test]
+    fn pieces_per_second_follows_play_time() {
+        let mut gs = GameState::default();
+        assert_eq!(gs.pieces_per_second(), 0.0);
+        // A hard drop every half second of game time
+        for _ in 0..6 {
+            (0..25).for_each(|_| {
+                gs.step(Duration::from_millis(20));
+            });
+            gs.on_button_pressed(Button::Drop);
+        }
+        assert_eq!(gs.stats.play_time_ms, 3000);
+        assert_eq!(gs.stats.pieces_placed, 6);
+        assert_eq!(gs.pieces_per_second(), 2.0);
+
+        // Paused time doesn't count
+        gs.on_button_pressed(Button::Pause);
+        gs.step(Duration::from_secs(10));
+        assert_eq!(gs.pieces_per_second(), 2.0);
+    }
+
+    #[test]
+    fn stepping_locks_piece() {
+        let mut gs = GameState::from_scenario(&["GGGG..GGGG"], &[PieceKind::T], PieceKind::O);
+        let dt = Duration::from_micros(16_667);
+        let mut outcomes = Vec::new();
+        for _ in 0..10_000 {
+            let outcome = gs.step(dt);
+            outcomes.push(outcome);
+            if outcome != StepOutcome::Stepped {
+                break;
+            }
+        }
+        assert_eq!(outcomes.last(), Some(&StepOutcome::LineCleared(1)));
+        assert!(outcomes.len() > 1);
+        assert_eq!(gs.active_piece.kind, PieceKind::T);
+
+        let mut outcome = StepOutcome::Stepped;
+        while outcome == StepOutcome::Stepped {
+            outcome = gs.step(dt);
+        }
+        assert_eq!(outcome, StepOutcome::Locked);
+    }
+
+    #[test]
+    fn applied_placement_matches_simulation() {
+        let mut gs = GameState::from_scenario(&["GGGGGG..GG"], &[PieceKind::T], PieceKind::O);
+        let placement = Placement {
+            kind: PieceKind::O,
+            rotation: Rotation::Rot0,
+            x: 6,
+        };
+        let mut target = Piece::new(PieceKind::O);
+        target.position = piece::GridPosition { x: 6, y: 0 };
+        let (expected, lines) = gs.simulate_lock(&target);
+
+        let result = gs.apply_placement(&placement);
+        assert_eq!(result.lines_cleared, lines);
+        assert_eq!(gs.grid.grid_map, expected.grid_map);
+        assert_eq!(gs.active_piece.kind, PieceKind::T);
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't fit")]
+    fn applying_placement_off_grid_panics() {
+        let mut gs = GameState::from_scenario(&[], &[], PieceKind::O);
+        gs.apply_placement(&Placement {
+            kind: PieceKind::O,
+            rotation: Rotation::Rot0,
+            x: GRID_COLUMNS as i32 - 1,
+        });
+    }
+
+    // Drops an I into the bottom row of a game on any grid size, with every
+    // other cell of that row filled. Returns the columns the I went down.
+    fn clear_bottom_row_with_i<const COLS: usize, const ROWS: usize>(
+        gs: &mut GameState<COLS, ROWS>,
+    ) -> Vec<i32> {
+        gs.set_queue(&[PieceKind::I]);
+        gs.spawn_next_piece();
+        let columns = gs.landing_columns();
+        (0..COLS as i32)
+            .filter(|x| !columns.contains(x))
+            .for_each(|x| gs.grid.set_cell(x, 0, PieceKind::Garbage));
+        gs.on_button_pressed(Button::Drop);
+        assert_eq!(gs.score().lines_cleared, 1);
+        assert_eq!(gs.grid.widths()[0], 0);
+        columns
+    }
+
+    #[test]
+    fn wide_and_mini_grids() {
+        let mut wide = GameState::<16, 30>::sized_with_seed(3);
+        assert_eq!(wide.active_piece.y_max(), 26 + 2);
+        let plain = wide.render_plain();
+        assert_eq!(plain.lines().count(), 26);
+        assert!(plain.lines().all(|line| line.len() == 16));
+        assert_eq!(clear_bottom_row_with_i(&mut wide), vec![6, 7, 8, 9]);
+        wide.queue_garbage_pattern(1, 50);
+        assert!(wide.pending_garbage.iter().all(|(_, hole)| *hole < 16));
+        assert!(wide.pending_garbage.iter().any(|(_, hole)| *hole >= 10));
+
+        let mut mini = GameState::<6, 14>::sized_with_seed(3);
+        assert_eq!(mini.render_plain().lines().count(), 10);
+        assert_eq!(clear_bottom_row_with_i(&mut mini), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn odd_grid_share_code() {
+        let mut gs = GameState::<5, 25>::sized_with_seed(1);
+        gs.grid.set_cell(4, 24, PieceKind::T);
+        let bytes = utils::decode_base64_url(&gs.to_share_code()).unwrap();
+        // 125 cells fill 63 bytes after the version, the last one padded
+        assert_eq!(bytes[63], (PieceKind::T as u8) << 4 | PieceKind::None as u8);
+        assert_eq!(bytes[64], gs.active_piece.kind as u8);
+    }
+
+    #[test]
+    fn span_collision_matches_cells() {
+        let board = ["G.........", "GG...G..GG", "GGG.GGG.GG"];
+        let mut gs = GameState::from_scenario(&board, &[], PieceKind::T);
+        for big_mode in [false, true] {
+            gs.set_big_mode(big_mode);
+            for kind in piece::PIECE_VEC {
+                let mut piece = Piece::new(kind);
+                for rotation in [
+                    Rotation::Rot0,
+                    Rotation::Rot90,
+                    Rotation::Rot90,
+                    Rotation::Rot90,
+                ] {
+                    piece.rotate(rotation);
+                    for (x, y) in
+                        (-3..GRID_COLUMNS as i32).flat_map(|x| (-3..6).map(move |y| (x, y)))
+                    {
+                        piece.position = piece::GridPosition { x, y };
+                        let by_cells = gs.piece_cells(&piece).into_iter().all(|(x, y)| {
+                            StandardGrid::is_within_bounds(x, y)
+                                && gs.grid.get_cell(x, y) == PieceKind::None
+                        });
+                        assert_eq!(gs.fits(&piece), by_cells);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn t_spin_slots_by_corners() {
+        // The T turns from pointing right to pointing up over the slot. Only
+        // one of the two corners above its center is filled.
+        let board = ["...G......", "GGG...GGGG", "GGGG.GGGGG"];
+        let mut gs = GameState::from_scenario(&board, &[], PieceKind::T);
+        gs.active_piece.rotate(Rotation::Rot90);
+        gs.active_piece.position = piece::GridPosition { x: 3, y: 0 };
+        gs.on_button_pressed(Button::RotateCounterClockwise);
+        assert!(!gs.last_rotation_kicked());
+        assert_eq!(gs.detect_spin(), Some(SpinKind::TSpinMini));
+        let result = gs.freeze_piece();
+        assert_eq!(result.lines_cleared, 1);
+        assert_eq!(gs.score().points, 200);
+
+        // With both of them filled it is a full T-spin
+        let board = ["...G.G....", "GGG...GGGG", "GGGG.GGGGG"];
+        let mut gs = GameState::from_scenario(&board, &[], PieceKind::T);
+        gs.active_piece.rotate(Rotation::Rot90);
+        gs.active_piece.position = piece::GridPosition { x: 3, y: 0 };
+        gs.on_button_pressed(Button::RotateCounterClockwise);
+        assert_eq!(gs.detect_spin(), Some(SpinKind::TSpinFull));
+    }
+
+    #[test]
+    fn t_spins_without_lines_score() {
+        // Same slots as `t_spin_slots_by_corners`, with no row left to fill
+        for (top, spin, points) in [
+            ("...G......", SpinKind::TSpinMini, 100),
+            ("...G.G....", SpinKind::TSpinFull, 400),
+        ] {
+            let board = [top, "GGG...GGG.", "GGGG.GGG.."];
+            let mut gs = GameState::from_scenario(&board, &[], PieceKind::T);
+            gs.active_piece.rotate(Rotation::Rot90);
+            gs.active_piece.position = piece::GridPosition { x: 3, y: 0 };
+            gs.on_button_pressed(Button::RotateCounterClockwise);
+            let result = gs.freeze_piece();
+            assert_eq!(result.spin, Some(spin));
+            assert_eq!(result.lines_cleared, 0);
+            assert_eq!(gs.score().points, points);
+        }
+    }
+
+    #[test]
+    fn late_kick_upgrades_mini_t_spin() {
+        // Pointing down above the pocket, the T only fits once kicked two
+        // cells down. Just one front corner is filled, which alone would
+        // make it a mini.
+        let board = [
+            "...G......",
+            "...GGG....",
+            ".G........",
+            "G.G..G....",
+            "G.........",
+            "GGG.G.....",
+        ];
+        let mut gs = GameState::from_scenario(&board, &[], PieceKind::T);
+        gs.active_piece.rotate(Rotation::Rot180);
+        gs.active_piece.position = piece::GridPosition { x: 2, y: 2 };
+        gs.on_button_pressed(Button::RotateCounterClockwise);
+        assert_eq!(gs.active_piece.position.y, 0);
+        assert_eq!(gs.last_kick, Some(UPGRADING_KICK));
+        assert_eq!(gs.tspin_corners(), (3, 1));
+        assert_eq!(gs.detect_spin(), Some(SpinKind::TSpinFull));
+    }
+
+    #[test]
+    fn moving_after_rotation_is_no_t_spin() {
+        let mut gs = GameState::from_scenario(&[], &[], PieceKind::T);
+        gs.drop_to_floor();
+        gs.on_button_pressed(Button::RotateClockwise);
+        gs.on_button_pressed(Button::MoveLeft);
+        assert!(!gs.last_action_was_tspin());
+        assert_eq!(gs.detect_spin(), None);
+    }
+
+    #[test]
+    fn wedged_piece_is_immobile() {
+        // A notch exactly the size of an O, closed off above
+        let board = ["GGGGGGGGGG", "GGGG..GGGG", "GGGG..GGGG"];
+        let mut gs = GameState::from_scenario(&board, &[], PieceKind::O);
+        assert!(!gs.is_piece_immobile());
+        gs.active_piece.position = piece::GridPosition { x: 4, y: 0 };
+        assert!(gs.is_piece_immobile());
+        // Opening the column to its left lets it slide out
+        gs.grid.set_cell(3, 0, PieceKind::None);
+        gs.grid.set_cell(3, 1, PieceKind::None);
+        assert!(!gs.is_piece_immobile());
+    }
+
+    #[test]
+    fn s_spin_is_detected() {
+        let mut gs = GameState::default();
+        // Slot for a flat S at the bottom, covered by a block at (4, 2)
+        (0..GRID_COLUMNS as i32).for_each(|x| {
+            gs.grid.set_cell(x, 0, PieceKind::I);
+            gs.grid.set_cell(x, 1, PieceKind::I);
+        });
+        [(3, 0), (4, 0), (3, 1), (4, 1), (5, 1)]
+            .into_iter()
+            .for_each(|(x, y)| gs.grid.set_cell(x, y, PieceKind::None));
+        gs.grid.set_cell(4, 2, PieceKind::I);
+
+        let mut piece = Piece::new(PieceKind::S);
+        piece.rotate(Rotation::Rot90);
+        piece.position = piece::GridPosition { x: 2, y: 1 };
+        gs.active_piece = piece;
+        assert_eq!(gs.detect_spin(), None);
+
+        gs.try_rotate(Rotation::Rot270);
+        assert_eq!(gs.detect_spin(), Some(SpinKind::Spin(PieceKind::S)));
+        let result = gs.freeze_piece();
+        assert_eq!(result.spin, Some(SpinKind::Spin(PieceKind::S)));
+        assert_eq!(result.lines_cleared, 1);
+    }
+
+    #[test]
+    fn delta_reconstructs_state_after_lock() {
+        let mut gs = GameState::default();
+        let mut stale = gs.clone();
+        gs.drop_piece();
+
+        let delta = gs.delta_from(&stale);
+        assert_eq!(delta.cells.len(), 4);
+        stale.apply_delta(&delta);
+        assert_eq!(stale.grid.grid_map, gs.grid.grid_map);
+        assert_eq!(stale.active_piece.kind, gs.active_piece.kind);
+        assert_eq!(stale.piece_id(), gs.piece_id());
+        assert_eq!(stale.current_piece_bag, gs.current_piece_bag);
+        assert_eq!(stale.next_piece_bag, gs.next_piece_bag);
+        assert_eq!(stale.rng_state(), gs.rng_state());
+    }
+
+    #[test]
+    fn spawn_fully_visible() {
+        let mut gs = GameState {
+            spawn_fully_visible: true,
+            ..Default::default()
+        };
+        for kind in piece::PIECE_VEC {
+            let piece = gs.spawn_piece(kind);
+            assert!(piece.y_max() < GRID_VISIBLE_ROWS as i32);
+            assert!(piece
+                .piece_dimensions
+                .piece_map
+                .iter()
+                .all(|(_, y)| piece.position.y + y < GRID_VISIBLE_ROWS as i32));
+        }
+    }
+
+    #[test]
+    fn attack_per_piece_after_tetris() {
+        let mut gs = GameState::default();
+        (0..4).for_each(|y| {
+            (0..GRID_COLUMNS as i32 - 1).for_each(|x| gs.grid.set_cell(x, y, PieceKind::L))
+        });
+        assert_eq!(gs.attack_per_piece(), 0.0);
+
+        // A piece that clears nothing, followed by a vertical I in the well
+        gs.active_piece = Piece::new(PieceKind::O);
+        gs.drop_piece();
+        let mut piece = Piece::new(PieceKind::I);
+        piece.rotate(Rotation::Rot90);
+        piece.position.x = GRID_COLUMNS as i32 - 2;
+        gs.active_piece = piece;
+        gs.drop_piece();
+
+        assert_eq!(gs.stats.pieces_placed, 2);
+        assert_eq!(gs.stats.total_attack_sent, 4);
+        assert_eq!(gs.attack_per_piece(), 2.0);
+    }
+
+    #[test]
+    fn ghost_column_marker() {
+        let mut gs = GameState {
+            ghost_style: GhostStyle::ColumnMarker,
+            ..Default::default()
+        };
+        let mut piece = Piece::new(PieceKind::T);
+        piece.position.x = 2;
+        gs.active_piece = piece;
+
+        assert_eq!(gs.landing_columns(), vec![2, 3, 4]);
+        let marker_columns: Vec<i32> = gs.ghost_cells().iter().map(|(x, _)| *x).collect();
+        assert_eq!(marker_columns, gs.landing_columns());
+
+        gs.ghost_style = GhostStyle::Full;
+        assert_eq!(gs.ghost_cells().len(), 4);
+        gs.ghost_style = GhostStyle::Off;
+        assert!(gs.ghost_cells().is_empty());
+    }
+
+    #[test]
+    fn clear_board_keeps_score() {
+        let mut gs = GameState::from_scenario(
+            &["ZZ.S..STTT", "IIII..IIII"],
+            &[PieceKind::T, PieceKind::L],
+            PieceKind::O,
+        );
+        gs.on_button_pressed(Button::Drop);
+        let score = gs.score();
+        let stats = gs.stats;
+        let queue = gs.current_piece_bag.clone();
+        let kind = gs.active_piece.kind;
+        assert_eq!(score.lines_cleared, 1);
+
+        gs.clear_board();
+        assert_eq!(gs.grid.grid_map, StandardGrid::new().grid_map);
+        assert_eq!(gs.score(), score);
+        assert_eq!(gs.stats.pieces_placed, stats.pieces_placed);
+        assert_eq!(gs.current_piece_bag, queue);
+        assert_eq!(gs.active_piece.kind, kind);
+    }
+
+    #[test]
+    fn pillar_blocks_column_access() {
+        let mut gs = GameState::from_scenario(&[], &[], PieceKind::O);
+        assert_eq!(gs.column_access(), [true; GRID_COLUMNS]);
+        (0..GRID_ROWS).for_each(|y| gs.grid.grid_map[y][6] = PieceKind::Garbage);
+        let mut expected = [true; GRID_COLUMNS];
+        expected[6..].fill(false);
+        assert_eq!(gs.column_access(), expected);
+    }
+
+    #[test]
+    fn hidden_ghost_is_not_rendered() {
+        let mut gs = GameState::from_scenario(&["OOOO......"], &[], PieceKind::T);
+        assert_eq!(gs.ghost_position().x, gs.active_piece.position.x);
+        assert_eq!(
+            gs.ghost_position().y,
+            gs.active_piece.position.y - gs.distance_to_drop()
+        );
+        assert_eq!(gs.render_plain().matches('#').count(), 4);
+
+        gs.show_ghost = false;
+        assert!(!gs.render_plain().contains('#'));
+        // The landing spot is still known to front-ends drawing their own ghost
+        let landing = gs.ghost_position();
+        gs.on_button_pressed(Button::Drop);
+        let (_, to) = gs.last_hard_drop().unwrap();
+        assert_eq!((to.x, to.y), (landing.x, landing.y));
+    }
+
+    #[test]
+    fn drop_to_floor_does_not_lock() {
+        let mut gs = GameState::default();
+        let id = gs.piece_id();
+        let expected = gs.distance_to_drop();
+
+        assert_eq!(gs.drop_to_floor(), expected);
+        assert_eq!(gs.distance_to_drop(), 0);
+        assert_eq!(gs.piece_id(), id);
+        assert_eq!(gs.grid.widths(), [0i32; GRID_ROWS]);
+    }
+
+    #[test]
+    fn rotation_kick_flag() {
+        let mut gs = GameState {
+            active_piece: Piece::new(PieceKind::T),
+            ..Default::default()
+        };
+        gs.try_rotate(Rotation::Rot90);
+        assert!(!gs.last_rotation_kicked());
+
+        // A vertical I against the left wall has to kick to lie flat
+        let mut piece = Piece::new(PieceKind::I);
+        piece.rotate(Rotation::Rot90);
+        piece.position = piece::GridPosition { x: -1, y: 5 };
+        gs.active_piece = piece;
+        gs.try_rotate(Rotation::Rot90);
+        assert!(gs.last_rotation_kicked());
+        assert!(gs.active_piece.x_min() >= 0);
+    }
+
+    #[test]
+    fn gravity_waits_for_line_clear_delay() {
+        let mut gs = GameState {
+            line_clear_delay: Duration::from_millis(50),
+            active_piece: Piece::new(PieceKind::O),
+            ..Default::default()
+        };
+        (0..2).for_each(|y| {
+            (0..GRID_COLUMNS as i32)
+                .filter(|x| !(4..=5).contains(x))
+                .for_each(|x| gs.grid.set_cell(x, y, PieceKind::L))
+        });
+        gs.drop_piece();
+        assert_eq!(gs.clearing_rows(), &[0, 1]);
+
+        // Nothing moves or spawns while the rows are being cleared
+        let (id, y) = (gs.piece_id(), gs.active_piece.position.y);
+        gs.apply_gravity();
+        gs.on_button_pressed(Button::Drop);
+        gs.on_update(Duration::from_millis(30));
+        gs.step(Duration::from_millis(19));
+        assert_eq!(gs.piece_id(), id);
+        assert_eq!(gs.active_piece.position.y, y);
+        assert_eq!(gs.grid.widths()[0], GRID_COLUMNS as i32);
+
+        gs.on_update(Duration::from_millis(1));
+        assert!(gs.clearing_rows().is_empty());
+        assert!(gs.piece_id() > id);
+        assert_eq!(gs.grid.widths(), [0i32; GRID_ROWS]);
+    }
+
+    #[test]
+    fn scenario_setup() {
+        let mut gs = GameState::from_scenario(
+            &["..........", "IIII.IIIII"],
+            &[PieceKind::T, PieceKind::S],
+            PieceKind::O,
+        );
+        assert_eq!(gs.grid.get_cell(4, 0), PieceKind::None);
+        assert_eq!(gs.grid.widths()[0], GRID_COLUMNS as i32 - 1);
+        assert_eq!(gs.active_piece.kind, PieceKind::O);
+
+        gs.drop_piece();
+        assert_eq!(gs.active_piece.kind, PieceKind::T);
+        gs.drop_piece();
+        assert_eq!(gs.active_piece.kind, PieceKind::S);
+    }
+
+    #[test]
+    #[should_panic]
+    fn scenario_rejects_bad_queue() {
+        GameState::from_scenario(&[], &[PieceKind::Garbage], PieceKind::O);
+    }
+
+    #[test]
+    fn clockwise_then_counter_clockwise_restores_piece() {
+        let mut gs = GameState {
+            active_piece: Piece::new(PieceKind::J),
+            ..Default::default()
+        };
+        let original = gs.active_piece.piece_dimensions.piece_map;
+
+        gs.on_button_pressed(Button::RotateClockwise);
+        assert_ne!(gs.active_piece.piece_dimensions.piece_map, original);
+        gs.on_button_pressed(Button::RotateCounterClockwise);
+        assert_eq!(gs.active_piece.piece_dimensions.piece_map, original);
+    }
+
+    #[test]
+    fn same_seed_same_pieces() {
+        let mut a = GameState::with_seed(42);
+        let mut b = GameState::with_seed(42);
+        for _ in 0..20 {
+            assert_eq!(a.active_piece.kind, b.active_piece.kind);
+            a.grid = StandardGrid::new();
+            b.grid = StandardGrid::new();
+            a.on_button_pressed(Button::Drop);
+            b.on_button_pressed(Button::Drop);
+        }
+        assert_eq!(a.current_piece_bag, b.current_piece_bag);
+    }
+
+    #[test]
+    fn garbage_pattern_is_deterministic() {
+        let pattern = garbage_pattern(7, 12);
+        assert_eq!(pattern, garbage_pattern(7, 12));
+        assert_ne!(pattern, garbage_pattern(8, 12));
+        assert!(pattern.iter().all(|col| *col < GRID_COLUMNS));
+
+        let mut gs = GameState::default();
+        gs.queue_garbage_pattern(7, 12);
+        gs.apply_pending_garbage();
+        for (row, hole_column) in pattern.iter().rev().enumerate() {
+            assert_eq!(
+                gs.grid.get_cell(*hole_column as i32, row as i32),
+                PieceKind::None
+            );
+            assert_eq!(gs.grid.widths()[row], GRID_COLUMNS as i32 - 1);
+        }
+    }
+
+    #[test]
+    fn every_bag_holds_all_seven_pieces() {
+        for seed in 0..10 {
+            let mut gs = GameState::with_seed(seed);
+            let mut drawn = Vec::new();
+            for _ in 0..70 {
+                drawn.push(gs.active_piece.kind);
+                gs.grid = StandardGrid::new();
+                gs.on_button_pressed(Button::Drop);
+            }
+            for bag in drawn.chunks(7) {
+                for kind in piece::PIECE_VEC {
+                    assert_eq!(bag.iter().filter(|k| **k == kind).count(), 1);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn first_piece_is_never_s_or_z() {
+        for seed in 0..500 {
+            let gs = GameState::with_seed(seed);
+            assert!(gs.no_bad_first_piece);
+            assert!(!matches!(gs.active_piece.kind, PieceKind::S | PieceKind::Z));
+        }
+    }
+
+    #[test]
+    fn rotate_180_on_the_floor() {
+        let mut gs = GameState {
+            active_piece: Piece::new(PieceKind::T),
+            ..Default::default()
+        };
+        gs.drop_to_floor();
+        gs.on_button_pressed(Button::Rotate180);
+
+        assert!(matches!(gs.active_piece.rotation, Rotation::Rot180));
+        let (x_min, x_max, y_min, y_max) = gs.active_piece.get_rect();
+        assert!(StandardGrid::is_rect_inside(x_min, x_max, y_min, y_max));
+    }
+
+    #[test]
+    fn lines_until_tetris_with_three_rows_ready() {
+        let mut gs = GameState::from_scenario(
+            &["LLLLLLLLL.", "LLLLLLLLL.", "LLLLLLLLL."],
+            &[],
+            PieceKind::I,
+        );
+        assert_eq!(gs.lines_until_tetris(), Some(1));
+        gs.grid = StandardGrid::new();
+        assert_eq!(gs.lines_until_tetris(), Some(4));
+    }
+
+    #[test]
+    fn reset_starts_a_fresh_game() {
+        let board = ["GGGG..GGGG"; 2];
+        let mut gs = GameState::from_scenario(&board, &[PieceKind::O, PieceKind::T], PieceKind::O);
+        gs.on_button_pressed(Button::Drop);
+        gs.on_button_pressed(Button::Hold);
+        gs.on_button_pressed(Button::Drop);
+        gs.queue_garbage(2, 0);
+        assert!(gs.score().points > 0);
+        assert_eq!(gs.combo(), -1);
+
+        gs.reset();
+        assert!(!gs.gameover);
+        assert!(gs
+            .grid
+            .grid_map
+            .iter()
+            .flatten()
+            .all(|kind| *kind == PieceKind::None));
+        assert_eq!(gs.score(), Score::default());
+        assert_eq!(gs.combo(), -1);
+        assert!(!gs.back_to_back());
+        assert_eq!(gs.hold_piece, None);
+        assert!(gs.pending_garbage.is_empty());
+        assert_eq!(gs.current_piece_bag.len(), 6);
+        assert_eq!(gs.next_piece_bag.len(), 7);
+    }
+
+    #[test]
+    fn restart_after_game_over() {
+        let mut gs = GameState::default();
+        gs.on_button_pressed(Button::Drop);
+        // Restart does nothing while the game is still running
+        gs.on_button_pressed(Button::Restart);
+        assert_eq!(gs.stats.pieces_placed, 1);
+
+        while !gs.gameover {
+            gs.on_button_pressed(Button::Drop);
+        }
+        gs.on_button_pressed(Button::Restart);
+        assert!(!gs.gameover);
+        assert_eq!(gs.grid.widths(), [0i32; GRID_ROWS]);
+        assert_eq!(gs.stats.pieces_placed, 0);
+
+        // The new game is playable
+        gs.on_button_pressed(Button::Drop);
+        assert_eq!(gs.stats.pieces_placed, 1);
+    }
+
+    #[test]
+    fn first_hold_pulls_from_queue() {
+        let mut gs = GameState::from_scenario(&[], &[PieceKind::T, PieceKind::S], PieceKind::L);
+        gs.on_button_pressed(Button::MoveLeft);
+        gs.on_button_pressed(Button::RotateClockwise);
+        gs.on_button_pressed(Button::Hold);
+
+        assert_eq!(gs.hold_piece, Some(PieceKind::L));
+        assert_eq!(gs.active_piece.kind, PieceKind::T);
+        let spawned = Piece::new(PieceKind::T);
+        assert!(matches!(gs.active_piece.rotation, Rotation::Rot0));
+        assert_eq!(gs.active_piece.position.x, spawned.position.x);
+        assert_eq!(gs.active_piece.position.y, spawned.position.y);
+
+        // Holding again is blocked until the next lock
+        gs.on_button_pressed(Button::Hold);
+        assert_eq!(gs.hold_piece, Some(PieceKind::L));
+        assert_eq!(gs.active_piece.kind, PieceKind::T);
+    }
+
+    #[test]
+    fn hold_swaps_with_held_piece() {
+        let mut gs = GameState::from_scenario(
+            &[],
+            &[PieceKind::T, PieceKind::S, PieceKind::I],
+            PieceKind::L,
+        );
+        gs.on_button_pressed(Button::Hold);
+        gs.on_button_pressed(Button::Drop);
+        assert_eq!(gs.active_piece.kind, PieceKind::S);
+
+        gs.on_button_pressed(Button::Hold);
+        assert_eq!(gs.hold_piece, Some(PieceKind::S));
+        assert_eq!(gs.active_piece.kind, PieceKind::L);
+        assert!(gs.hold_used_this_turn);
+        gs.on_button_pressed(Button::Drop);
+        assert!(!gs.hold_used_this_turn);
+        assert_eq!(gs.active_piece.kind, PieceKind::I);
+    }
+
+    #[test]
+    fn can_hold_once_per_piece() {
+        let mut gs = GameState::from_scenario(&[], &[PieceKind::O, PieceKind::S], PieceKind::T);
+        assert!(gs.can_hold());
+        gs.on_button_pressed(Button::Hold);
+        assert!(!gs.can_hold());
+        gs.on_button_pressed(Button::Drop);
+        assert!(gs.can_hold());
+
+        gs.hold_enabled = false;
+        assert!(!gs.can_hold());
+    }
+
+    fn drop_vertical_i_in_right_well(gs: &mut GameState) {
+        gs.on_button_pressed(Button::RotateClockwise);
+        (0..GRID_COLUMNS).for_each(|_| gs.on_button_pressed(Button::MoveRight));
+        gs.on_button_pressed(Button::Drop);
+    }
+
+    #[test]
+    fn back_to_back_survives_clearless_lock() {
+        let well_rows = ["LLLLLLLLL."; 8];
+        let queue = [PieceKind::O, PieceKind::I];
+        let mut gs = GameState::from_scenario(&well_rows, &queue, PieceKind::I);
+        drop_vertical_i_in_right_well(&mut gs);
+        assert_eq!(gs.stats.total_attack_sent, 4);
+        gs.on_button_pressed(Button::Drop);
+        assert_eq!(gs.combo, -1);
+        assert!(gs.back_to_back);
+        drop_vertical_i_in_right_well(&mut gs);
+        // The second tetris still gets the back-to-back bonus line
+        assert_eq!(gs.stats.total_attack_sent, 4 + 5);
+    }
+
+    #[test]
+    fn multiplier_after_back_to_back_tetrises() {
+        let well_rows = ["LLLLLLLLL."; 8];
+        let mut gs = GameState::from_scenario(&well_rows, &[PieceKind::I], PieceKind::I);
+        assert_eq!(gs.current_multiplier(), 1.0);
+
+        drop_vertical_i_in_right_well(&mut gs);
+        assert_eq!(gs.current_multiplier(), 1.5 * 1.25);
+        drop_vertical_i_in_right_well(&mut gs);
+        assert_eq!(gs.grid.widths(), [0i32; GRID_ROWS]);
+        assert_eq!(gs.current_multiplier(), 1.5 * 1.5);
+    }
+
+    #[test]
+    fn tetris_on_level_one() {
+        let well_rows = ["LLLLLLLLL."; 4];
+        let mut gs = GameState::from_scenario(&well_rows, &[], PieceKind::I);
+        drop_vertical_i_in_right_well(&mut gs);
+        let (from, to) = gs.last_hard_drop().unwrap();
+        assert_eq!(
+            gs.score(),
+            Score {
+                points: 800 + 2 * (from.y - to.y) as u32,
+                lines_cleared: 4,
+                level: 1
+            }
+        );
+    }
+
+    #[test]
+    fn single_line_clears() {
+        let mut gs = GameState::from_scenario(&["IIII..IIII"], &[], PieceKind::O);
+        let distance = gs.distance_to_drop() as u32;
+        gs.on_button_pressed(Button::Drop);
+        assert_eq!(gs.score().points, 100 + 2 * distance);
+        assert_eq!(gs.score().lines_cleared, 1);
+    }
+
+    #[test]
+    fn gravity_speeds_up_with_level() {
+        let mut gs = GameState::default();
+        assert_eq!(gs.gravity_interval(), Duration::from_secs(1));
+        let mut last = gs.gravity_interval();
+        for level in 2..=13 {
+            gs.score.level = level;
+            assert!(gs.gravity_interval() < last);
+            last = gs.gravity_interval();
+        }
+        for level in [14, 20, 1000] {
+            gs.score.level = level;
+            assert_eq!(gs.gravity_interval(), MIN_GRAVITY_INTERVAL);
+        }
+    }
+
+    #[test]
+    fn gravity_accumulator_crossing_threshold() {
+        let mut gs = GameState::default();
+        let y = gs.active_piece.position.y;
+        gs.set_gravity_accumulator_ms(990);
+        gs.advance_gravity(5);
+        assert_eq!(gs.active_piece.position.y, y);
+        gs.advance_gravity(15);
+        assert_eq!(gs.active_piece.position.y, y - 1);
+        assert_eq!(gs.gravity_accumulator_ms(), 10);
+    }
+
+    #[test]
+    fn best_single_move_clear_on_tetris_ready_board() {
+        let board = ["IIIIIIIII.", "IIIIIIIII.", "IIIIIIIII.", "IIIIIIIII."];
+        let gs = GameState::from_scenario(&board, &[], PieceKind::I);
+        assert_eq!(gs.best_single_move_clear(), 4);
+        let gs = GameState::from_scenario(&board, &[], PieceKind::O);
+        assert_eq!(gs.best_single_move_clear(), 0);
+    }
+
+    #[test]
+    fn mirrored_controls_swap_directions() {
+        let mut gs = GameState {
+            mirror_controls: true,
+            ..Default::default()
+        };
+        let x = gs.active_piece.position.x;
+        gs.on_button_pressed(Button::MoveLeft);
+        assert_eq!(gs.active_piece.position.x, x + 1);
+        gs.on_button_pressed(Button::RotateClockwise);
+        assert!(matches!(gs.active_piece.rotation, Rotation::Rot270));
+    }
+
+    #[test]
+    fn planned_back_to_back_tetrises() {
+        let gs = GameState::from_scenario(&["IIIIIIIII."; 8], &[], PieceKind::O);
+        let tetris = Placement {
+            kind: PieceKind::I,
+            rotation: Rotation::Rot90,
+            x: 8,
+        };
+        // The second tetris is back-to-back and sends an extra line
+        assert_eq!(gs.planned_attack(&[tetris]), 4);
+        assert_eq!(gs.planned_attack(&[tetris, tetris]), 9);
+        // Planning leaves the live board alone
+        assert_eq!(gs.grid.aggregate_height(), 9 * 8);
+    }
+
+    #[test]
+    fn string_repr_round_trip() {
+        let mut gs = GameState::default();
+        gs.on_button_pressed(Button::Drop);
+        gs.on_button_pressed(Button::MoveLeft);
+        gs.on_button_pressed(Button::Drop);
+        gs.on_button_pressed(Button::RotateClockwise);
+        gs.on_button_pressed(Button::MoveRight);
+        gs.grid.set_cell(3, 0, PieceKind::Garbage);
+
+        let repr = gs.to_string_repr();
+        let parsed = GameState::from_string_repr(&repr).unwrap();
+        assert_eq!(parsed.grid.grid_map, gs.grid.grid_map);
+        assert_eq!(parsed.active_piece.kind, gs.active_piece.kind);
+        assert_eq!(
+            parsed.active_piece.piece_dimensions.piece_map,
+            gs.active_piece.piece_dimensions.piece_map
+        );
+        assert_eq!(parsed.active_piece.position.x, gs.active_piece.position.x);
+        assert_eq!(parsed.active_piece.position.y, gs.active_piece.position.y);
+        assert_eq!(parsed.current_piece_bag, gs.current_piece_bag);
+        assert_eq!(parsed.next_piece_bag, gs.next_piece_bag);
+        assert_eq!(parsed.to_string_repr(), repr);
+    }
+
+    #[test]
+    fn string_repr_errors() {
+        let repr = GameState::default().to_string_repr();
+        assert_eq!(
+            GameState::from_string_repr("").unwrap_err(),
+            ParseError::WrongSectionCount
+        );
+        assert_eq!(
+            GameState::from_string_repr(&repr.replacen('.', "x", 1)).unwrap_err(),
+            ParseError::InvalidBoard
+        );
+        let (board, _) = repr.split_once(';').unwrap();
+        assert_eq!(
+            GameState::from_string_repr(&format!("{board};T,0,4,y;IJ;LO")).unwrap_err(),
+            ParseError::InvalidNumber("y".into())
+        );
+        assert_eq!(
+            GameState::from_string_repr(&format!("{board};T,0,4,18;IG;LO")).unwrap_err(),
+            ParseError::InvalidPiece('G')
+        );
+    }
+
+    #[test]
+    fn visible_field_matches_grid() {
+        let mut gs = GameState::from_scenario(&["..T.......", "IIIIIIIII."], &[], PieceKind::O);
+        gs.grid.set_cell(0, GRID_ROWS as i32 - 1, PieceKind::Z);
+        let field = gs.visible_field();
+        assert_eq!(field[..], gs.grid.grid_map[..GRID_VISIBLE_ROWS]);
+        assert_eq!(field[1][2], PieceKind::T);
+        // The active piece isn't part of the field
+        assert!(field.iter().flatten().all(|kind| *kind != PieceKind::O));
+    }
+
+    #[test]
+    fn lock_delay_eventually_locks() {
+        let mut gs = GameState::from_scenario(&[], &[PieceKind::T], PieceKind::O);
+        gs.lock_delay_ticks = 3;
+        gs.drop_to_floor();
+        let id = gs.piece_id();
+        (0..3).for_each(|_| gs.tick_lock_delay());
+        assert_eq!(gs.piece_id(), id);
+        gs.tick_lock_delay();
+        assert_ne!(gs.piece_id(), id);
+        assert_eq!(gs.grid.widths()[0], 2);
+    }
+
+    #[test]
+    fn moving_restarts_lock_delay() {
+        let mut gs = GameState::from_scenario(&[], &[PieceKind::T], PieceKind::O);
+        gs.lock_delay_ticks = 3;
+        gs.drop_to_floor();
+        gs.apply_gravity();
+        let id = gs.piece_id();
+        for i in 0..MAX_LOCK_RESETS {
+            (0..3).for_each(|_| gs.tick_lock_delay());
+            let button = [Button::MoveLeft, Button::MoveRight][i as usize % 2];
+            gs.on_button_pressed(button);
+        }
+        assert_eq!(gs.piece_id(), id);
+        // Once the resets are used up the piece locks on schedule
+        gs.on_button_pressed(Button::MoveLeft);
+        (0..4).for_each(|_| gs.tick_lock_delay());
+        assert_ne!(gs.piece_id(), id);
+    }
+
+    #[test]
+    fn logged_drop_events() {
+        let mut gs = GameState::from_scenario(&["IIII..IIII"], &[PieceKind::T], PieceKind::O);
+        assert_eq!(
+            gs.apply_button_logged(Button::MoveLeft),
+            vec![GameEvent::PieceMoved]
+        );
+        gs.on_button_pressed(Button::MoveRight);
+        assert_eq!(
+            gs.apply_button_logged(Button::Drop),
+            vec![
+                GameEvent::PieceLocked(PieceKind::O),
+                GameEvent::LinesCleared(1),
+                GameEvent::PieceSpawned(PieceKind::T),
+            ]
+        );
+        assert_eq!(
+            gs.apply_button_logged(Button::Hold),
+            vec![
+                GameEvent::PieceHeld(PieceKind::T),
+                GameEvent::PieceSpawned(gs.active_piece.kind)
+            ]
+        );
+        assert_eq!(
+            gs.apply_button_logged(Button::Quit),
+            vec![GameEvent::GameOver]
+        );
+    }
+
+    #[test]
+    fn logged_delayed_line_clear() {
+        let mut gs = GameState::from_scenario(&["IIII..IIII"], &[PieceKind::T], PieceKind::O);
+        gs.line_clear_delay = Duration::from_millis(20);
+        assert_eq!(
+            gs.apply_button_logged(Button::Drop),
+            vec![GameEvent::PieceLocked(PieceKind::O)]
+        );
+        assert_eq!(gs.on_update_logged(Duration::from_millis(10)), vec![]);
+        assert_eq!(
+            gs.on_update_logged(Duration::from_millis(10)),
+            vec![
+                GameEvent::LinesCleared(1),
+                GameEvent::PieceSpawned(PieceKind::T),
+            ]
+        );
+    }
+
+    #[test]
+    fn preview_matches_spawned_pieces() {
+        let mut gs = GameState::with_seed(3);
+        let preview = gs.preview(10);
+        assert_eq!(preview.len(), 10);
+        let spawned: Vec<PieceKind> = (0..10)
+            .map(|_| {
+                gs.grid = StandardGrid::new();
+                gs.on_button_pressed(Button::Drop);
+                gs.active_piece.kind
+            })
+            .collect();
+        assert_eq!(preview, spawned);
+        assert!(gs.preview(100).len() <= 14);
+    }
+
+    #[test]
+    fn instant_soft_drop_lock() {
+        let mut gs = GameState::from_scenario(&[], &[PieceKind::T], PieceKind::O);
+        gs.soft_drop_lock = SoftDropLock::Instant;
+        let id = gs.piece_id();
+        while gs.piece_id() == id {
+            gs.on_button_pressed(Button::MoveDown);
+        }
+        assert_eq!(gs.grid.widths()[0], 2);
+    }
+
+    #[test]
+    fn delayed_soft_drop_lock() {
+        let mut gs = GameState::from_scenario(&[], &[PieceKind::T], PieceKind::O);
+        gs.soft_drop_lock = SoftDropLock::Delayed;
+        let id = gs.piece_id();
+        (0..GRID_ROWS).for_each(|_| gs.on_button_pressed(Button::MoveDown));
+        assert_eq!(gs.piece_id(), id);
+        assert_eq!(gs.lock_timer, Some(gs.lock_delay_ticks));
+    }
+
+    #[test]
+    fn best_fit_fills_flat_gap() {
+        let gs = GameState::from_scenario(&["GGG....GGG"], &[], PieceKind::O);
+        let placement = gs.best_fit(PieceKind::I);
+        let mut piece = Piece::new(PieceKind::I);
+        piece.rotate(placement.rotation);
+        piece.position.x = placement.x;
+        let piece = gs.drop_from_top(piece).unwrap();
+        let cells = piece
+            .piece_dimensions
+            .piece_map
+            .map(|(x, y)| (piece.position.x + x, piece.position.y + y));
+        assert_eq!(cells, [(3, 0), (4, 0), (5, 0), (6, 0)]);
+    }
+
+    #[test]
+    fn combo_counts_consecutive_clears() {
+        let board = ["GGGG..GGGG"; 6];
+        let mut gs = GameState::from_scenario(&board, &[PieceKind::O; 3], PieceKind::O);
+        assert_eq!(gs.combo(), -1);
+        for combo in 0..3 {
+            gs.on_button_pressed(Button::Drop);
+            assert_eq!(gs.combo(), combo);
+            assert!(!gs.back_to_back());
+        }
+        gs.on_button_pressed(Button::Drop);
+        assert_eq!(gs.combo(), -1);
+    }
+
+    #[test]
+    fn back_to_back_needs_difficult_clears() {
+        let mut rows = vec!["LLLLLLLLL."; 4];
+        rows.insert(0, "GGGG..GGG.");
+        rows.insert(0, "GGGG..GGG.");
+        let queue = [PieceKind::O];
+        let mut gs = GameState::from_scenario(&rows, &queue, PieceKind::I);
+        drop_vertical_i_in_right_well(&mut gs);
+        assert!(gs.back_to_back());
+        assert_eq!(gs.combo(), 0);
+        // A plain double ends back-to-back but continues the combo
+        gs.grid.set_cell(9, 0, PieceKind::Garbage);
+        gs.grid.set_cell(9, 1, PieceKind::Garbage);
+        gs.on_button_pressed(Button::Drop);
+        assert!(!gs.back_to_back());
+        assert_eq!(gs.combo(), 1);
+    }
+
+    #[test]
+    fn bag_boundary_after_seven_pieces() {
+        let mut gs = GameState::with_seed(5);
+        for _ in 0..6 {
+            assert!(!gs.is_bag_boundary_next());
+            gs.grid = StandardGrid::new();
+            gs.on_button_pressed(Button::Drop);
+        }
+        assert!(gs.is_bag_boundary_next());
+        gs.on_button_pressed(Button::Drop);
+        assert!(!gs.is_bag_boundary_next());
+    }
+
+    #[test]
+    fn perfect_clear_is_detected() {
+        let board = ["GGGG..GGGG", "GGGG..GGGG"];
+        let mut gs = GameState::from_scenario(&board, &[PieceKind::O], PieceKind::O);
+        gs.on_button_pressed(Button::Drop);
+        assert!(gs.last_clear_was_perfect());
+        gs.on_button_pressed(Button::Drop);
+        assert!(!gs.last_clear_was_perfect());
+    }
+
+    #[test]
+    fn residual_block_is_no_perfect_clear() {
+        let board = ["G.........", "GGGG..GGGG", "GGGG..GGGG"];
+        let mut gs = GameState::from_scenario(&board, &[], PieceKind::O);
+        gs.on_button_pressed(Button::Drop);
+        assert_eq!(gs.score().lines_cleared, 2);
+        assert!(!gs.last_clear_was_perfect());
+    }
+
+    #[test]
+    fn filling_the_gap_is_flatter() {
+        let gs = GameState::from_scenario(&["GGGG..GGGG"; 2], &[], PieceKind::O);
+        let mut in_gap = Piece::new(PieceKind::O);
+        in_gap.position = piece::GridPosition { x: 4, y: 0 };
+        let mut on_top = in_gap.clone();
+        on_top.position = piece::GridPosition { x: 0, y: 2 };
+        assert_eq!(gs.flatness_after(&in_gap), 1.0);
+        assert!(gs.flatness_after(&on_top) < gs.flatness_after(&in_gap));
+    }
+
+    #[test]
+    fn paused_game_stands_still() {
+        let mut gs = GameState::default();
+        let (x, y) = (gs.active_piece.position.x, gs.active_piece.position.y);
+        gs.on_button_pressed(Button::Pause);
+        assert!(gs.paused);
+        gs.apply_gravity();
+        gs.advance_gravity(10_000);
+        gs.on_button_pressed(Button::MoveLeft);
+        gs.on_button_pressed(Button::Drop);
+        assert_eq!(gs.active_piece.position.x, x);
+        assert_eq!(gs.active_piece.position.y, y);
+
+        gs.on_button_pressed(Button::Pause);
+        gs.apply_gravity();
+        assert_eq!(gs.active_piece.position.y, y - 1);
+    }
+
+    #[test]
+    fn hard_drop_trail() {
+        let mut gs = GameState::from_scenario(&["GGGG..GGGG"], &[PieceKind::T], PieceKind::O);
+        let distance = gs.distance_to_drop();
+        let start = gs.active_piece.position;
+        gs.on_button_pressed(Button::Drop);
+        let (from, to) = gs.last_hard_drop().unwrap();
+        assert_eq!((from.x, from.y), (start.x, start.y));
+        assert_eq!((to.x, to.y), (start.x, start.y - distance));
+        gs.on_button_pressed(Button::MoveLeft);
+        assert!(gs.last_hard_drop().is_none());
+    }
+
+    #[test]
+    fn soft_drop_scores_per_cell() {
+        let mut gs = GameState::from_scenario(&[], &[], PieceKind::O);
+        gs.drop_to_floor();
+        gs.active_piece.position.y += 3;
+        (0..3).for_each(|_| gs.on_button_pressed(Button::SoftDrop));
+        assert_eq!(gs.score().points, 3);
+        gs.on_button_pressed(Button::SoftDrop);
+        assert_eq!(gs.score().points, 3);
+        // A plain MoveDown doesn't score
+        gs.active_piece.position.y += 1;
+        gs.on_button_pressed(Button::MoveDown);
+        assert_eq!(gs.score().points, 3);
+    }
+
+    #[test]
+    fn held_soft_drop_repeats() {
+        let mut gs = GameState::from_scenario(&[], &[], PieceKind::O);
+        gs.soft_drop_repeat_ms = Some(10);
+        gs.drop_to_floor();
+        gs.active_piece.position.y += 8;
+        let y = gs.active_piece.position.y;
+        gs.on_button_pressed(Button::SoftDrop);
+        // Short of a gravity step, only the repeat moves the piece
+        for _ in 0..3 {
+            gs.advance_gravity(10);
+        }
+        assert_eq!(gs.active_piece.position.y, y - 4);
+        assert_eq!(gs.score().points, 4);
+        gs.advance_gravity(25);
+        assert_eq!(gs.active_piece.position.y, y - 6);
+        assert_eq!(gs.score().points, 6);
+
+        gs.on_button_released(Button::SoftDrop);
+        gs.advance_gravity(50);
+        assert_eq!(gs.active_piece.position.y, y - 6);
+        assert_eq!(gs.score().points, 6);
+    }
+
+    #[test]
+    fn cleared_row_span() {
+        let mut board = vec!["GGGGGGGGGG"; 4];
+        board.extend(["GGGGGGGGG.", "GGGGGGGGG."]);
+        let mut gs = GameState::from_scenario(&board, &[], PieceKind::O);
+        assert_eq!(gs.clear_full_rows(), 4);
+        assert_eq!(gs.last_cleared_row_span(), Some((2, 5)));
+        assert_eq!(gs.clear_full_rows(), 0);
+        assert_eq!(gs.last_cleared_row_span(), None);
+    }
+
+    #[test]
+    fn hard_drop_scores_two_points_per_cell() {
+        let mut gs = GameState::from_scenario(&[], &[], PieceKind::O);
+        gs.drop_to_floor();
+        gs.active_piece.position.y += 5;
+        gs.on_button_pressed(Button::Drop);
+        assert_eq!(gs.score().points, 10);
+    }
+
+    #[test]
+    fn tuck_under_overhang() {
+        let board = ["GGG.......", "..........", ".........."];
+        let gs = GameState::from_scenario(&board, &[], PieceKind::O);
+        let depths = gs.reachable_depths();
+        let mut naive = gs.active_piece.clone();
+        naive.position.x = 0;
+        let naive = gs.drop_from_top(naive).unwrap();
+        assert_eq!(naive.y_min(), 3);
+        assert_eq!(depths[0], 0);
+        assert_eq!(depths[GRID_COLUMNS - 2], 0);
+        // The O can't have its left edge in the last column
+        assert_eq!(depths[GRID_COLUMNS - 1], -1);
+    }
+
+    #[test]
+    fn level_up_every_ten_lines() {
+        let mut gs = GameState::from_scenario(&["IIII..IIII"], &[], PieceKind::O);
+        gs.score.lines_cleared = 9;
+        gs.on_button_pressed(Button::Drop);
+        assert_eq!(gs.score().lines_cleared, 10);
+        assert_eq!(gs.score().level, 2);
+    }
+
+    #[test]
+    fn plain_render() {
+        let mut gs = GameState::from_scenario(&["GGG.GGGGGG"], &[], PieceKind::O);
+        gs.active_piece.position.y = 3;
+        let expected = "..........\n".repeat(15)
+            + "....OO....\n\
+               ....OO....\n\
+               ....##....\n\
+               ....##....\n\
+               GGG.GGGGGG\n";
+        assert_eq!(gs.render_plain(), expected);
+        assert!(!gs.render_plain().contains('\x1b'));
+    }
+
+    #[test]
+    fn big_mode_moves_and_clears_at_double_scale() {
+        let mut gs = GameState::from_scenario(&["GGGGGG...."; 4], &[], PieceKind::O);
+        gs.set_big_mode(true);
+        assert_eq!(gs.landing_columns(), vec![2, 3, 4, 5]);
+        let x = gs.active_piece.position.x;
+        gs.on_button_pressed(Button::MoveRight);
+        assert_eq!(gs.active_piece.position.x, x + 2);
+        gs.on_button_pressed(Button::MoveRight);
+        assert_eq!(gs.landing_columns(), vec![6, 7, 8, 9]);
+        gs.on_button_pressed(Button::Drop);
+        assert_eq!(gs.score().lines_cleared, 4);
+
+        // Rows stay put unless the scaled piece fills every cell
+        let mut gs = GameState::from_scenario(&["GGGG......"; 4], &[], PieceKind::O);
+        gs.set_big_mode(true);
+        (0..2).for_each(|_| gs.on_button_pressed(Button::MoveRight));
+        gs.on_button_pressed(Button::Drop);
+        assert_eq!(gs.score().lines_cleared, 0);
+        assert_eq!(gs.grid.get_cell(9, 3), PieceKind::O);
+        assert_eq!(gs.grid.get_cell(5, 0), PieceKind::None);
+    }
+
+    // `rows` with every cell doubled along both axes
+    fn scaled_up(rows: &[&str]) -> Vec<String> {
+        rows.iter()
+            .flat_map(|row| {
+                let wide: String = row.chars().flat_map(|c| [c, c]).collect();
+                [wide.clone(), wide]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn big_mode_simulated_locks_match_real_ones() {
+        let rows = scaled_up(&["GGG..", "GGG.."]);
+        let board: Vec<&str> = rows.iter().map(String::as_str).collect();
+        let mut gs = GameState::from_scenario(&board, &[], PieceKind::O);
+        gs.set_big_mode(true);
+        let landings = gs.all_landings();
+        assert_eq!(landings.len(), 4);
+        let best = gs.best_fit(PieceKind::O);
+        assert_eq!(best.x, 6);
+        let landing = landings.iter().find(|p| p.position.x == best.x).unwrap();
+        let (grid, lines) = gs.simulate_lock(landing);
+        assert_eq!(lines, 4);
+
+        gs.lock_placement(&best).unwrap();
+        assert_eq!(gs.score().lines_cleared, 4);
+        assert_eq!(gs.grid.grid_map, grid.grid_map);
+    }
+
+    #[test]
+    fn big_mode_reachable_depths_stay_on_blocks() {
+        let rows = scaled_up(&["GG...", ".....", "....."]);
+        let board: Vec<&str> = rows.iter().map(String::as_str).collect();
+        let mut gs = GameState::from_scenario(&board, &[], PieceKind::O);
+        gs.set_big_mode(true);
+        // The O moves a whole block at a time and still tucks under the
+        // overhang
+        assert_eq!(
+            gs.reachable_depths(),
+            vec![0, -1, 0, -1, 0, -1, 0, -1, -1, -1]
+        );
+    }
+
+    #[test]
+    fn big_mode_t_spin_corners_are_blocks() {
+        // A T pointing down into a slot one block wide, both front corners
+        // and one back corner filled
+        let rows = scaled_up(&[".G...", "G...G", "GG.GG"]);
+        let board: Vec<&str> = rows.iter().map(String::as_str).collect();
+        let mut gs = GameState::from_scenario(&board, &[], PieceKind::T);
+        gs.set_big_mode(true);
+        gs.active_piece.rotate(Rotation::Rot180);
+        gs.active_piece.position = piece::GridPosition { x: 2, y: 0 };
+        assert!(gs.fits(&gs.active_piece));
+        assert_eq!(gs.tspin_corners(), (3, 2));
+        assert!(gs.is_tspin_position());
+    }
+
+    #[test]
+    fn tall_placement_is_riskier() {
+        let queue = [PieceKind::S, PieceKind::Z, PieceKind::S, PieceKind::Z];
+        let mut gs = GameState::from_scenario(&["GGGGGGGGG."; 18], &queue, PieceKind::I);
+        gs.spawn_fully_visible = true;
+        // A vertical I in the first column or down the well on the right
+        let tall = Placement {
+            kind: PieceKind::I,
+            rotation: Rotation::Rot90,
+            x: -1,
+        };
+        let flat = Placement { x: 8, ..tall };
+        let tall_risk = gs.topout_risk(&tall, 3);
+        let flat_risk = gs.topout_risk(&flat, 3);
+        assert!(tall_risk > flat_risk, "{tall_risk} <= {flat_risk}");
+        assert_eq!(flat_risk, 0.0);
+        // The tall stack tops out on the second piece after it, halfway
+        // through the lookahead, and not at all within one piece
+        assert_eq!(tall_risk, 0.5);
+        assert_eq!(gs.topout_risk(&tall, 1), 0.0);
+        let off_grid = Placement { x: 20, ..tall };
+        assert_eq!(gs.topout_risk(&off_grid, 3), 1.0);
+    }
+
+    #[test]
+    fn distance_to_drop_beside_grid() {
+        let mut gs = GameState::from_scenario(&["GGGGGGGGGG"], &[], PieceKind::T);
+        let y_min = gs.active_piece.y_min();
+        for x in [-10, GRID_COLUMNS as i32 + 3] {
+            gs.active_piece.position.x = x;
+            assert_eq!(gs.distance_to_drop(), y_min);
+        }
+    }
+
+    #[test]
+    fn block_out_at_spawn() {
+        let mut gs = GameState::from_scenario(&[], &[PieceKind::O], PieceKind::T);
+        let spawn = Piece::new(PieceKind::O);
+        gs.grid
+            .set_cell(spawn.x_min(), spawn.y_min(), PieceKind::Garbage);
+        gs.drop_piece();
+        assert!(gs.is_game_over());
+        assert_eq!(gs.game_over_reason(), Some(GameOverReason::BlockOut));
+
+        gs.on_button_pressed(Button::Restart);
+        assert!(!gs.is_game_over());
+        assert_eq!(gs.game_over_reason(), None);
+    }
+
+    #[test]
+    fn lock_out_above_visible_field() {
+        let mut gs = GameState::from_scenario(&[], &[], PieceKind::O);
+        gs.active_piece.position.y = GRID_VISIBLE_ROWS as i32;
+        gs.freeze_piece();
+        assert!(gs.is_game_over());
+        assert_eq!(gs.game_over_reason(), Some(GameOverReason::LockOut));
+
+        let mut gs = GameState::default();
+        gs.on_button_pressed(Button::Quit);
+        assert!(gs.is_game_over());
+        assert_eq!(gs.game_over_reason(), None);
+    }
+
+    #[test]
+    fn garbage_pushes_stack_up() {
+        let mut gs = GameState::from_scenario(&["..TTT.....", "...T......"], &[], PieceKind::O);
+        gs.add_garbage(3, 6);
+        assert!(!gs.gameover);
+        assert_eq!(gs.grid.get_cell(3, 3), PieceKind::T);
+        assert_eq!(gs.grid.get_cell(2, 4), PieceKind::T);
+        assert_eq!(gs.grid.get_cell(3, 0), PieceKind::Garbage);
+        for row in 0..3 {
+            assert_eq!(gs.grid.get_cell(6, row), PieceKind::None);
+            assert_eq!(gs.grid.widths()[row as usize], GRID_COLUMNS as i32 - 1);
+        }
+
+        // A piece resting on the stack rides up with it
+        gs.drop_to_floor();
+        let y = gs.active_piece.position.y;
+        gs.add_garbage(2, 0);
+        assert_eq!(gs.active_piece.position.y, y + 2);
+        assert!(!gs.gameover);
+
+        gs.add_garbage(GRID_ROWS - 6, 0);
+        assert!(gs.gameover);
+    }
+
+    #[test]
+    fn empty_start_waits_for_spawn() {
+        let mut gs = GameState::empty_with_seed(7);
+        assert!(!gs.has_active_piece());
+        assert_eq!(gs.render_plain(), "..........\n".repeat(GRID_VISIBLE_ROWS));
+        let y = gs.active_piece.position.y;
+        gs.advance_gravity(5000);
+        gs.on_button_pressed(Button::Drop);
+        assert_eq!(gs.active_piece.position.y, y);
+        assert_eq!(gs.stats.pieces_placed, 0);
+
+        gs.spawn_next_piece();
+        assert!(gs.has_active_piece());
+        let seeded = GameState::with_seed(7);
+        assert_eq!(gs.active_piece.kind, seeded.active_piece.kind);
+        assert_eq!(gs.preview(6), seeded.preview(6));
+        assert!(GameState::with_seed(7).has_active_piece());
+    }
+
+    #[test]
+    fn nudge_piece_up() {
+        let mut gs = GameState::from_scenario(&[], &[], PieceKind::T);
+        gs.drop_to_floor();
+        let y = gs.active_piece.position.y;
+        gs.try_move(Direction::Up);
+        assert_eq!(gs.active_piece.position.y, y + 1);
+        // Blocked once the piece reaches the ceiling
+        (0..GRID_ROWS).for_each(|_| gs.try_move(Direction::Up));
+        assert_eq!(gs.active_piece.y_max(), GRID_ROWS as i32 - 1);
+    }
+
+    #[test]
+    fn share_code_round_trip() {
+        let mut gs = GameState::with_seed(42);
+        gs.queue_garbage(3, 4);
+        gs.apply_pending_garbage();
+        for button in [Button::Drop, Button::Hold, Button::MoveLeft, Button::Drop] {
+            gs.on_button_pressed(button);
+        }
+        gs.on_button_pressed(Button::RotateClockwise);
+        gs.score.points = 12345;
+        gs.combo = 2;
+
+        let code = gs.to_share_code();
+        assert!(code
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        let restored = GameState::from_share_code(&code).unwrap();
+        assert_eq!(restored.to_string_repr(), gs.to_string_repr());
+        assert_eq!(restored.to_share_code(), code);
+        assert_eq!(restored.hold_piece, gs.hold_piece);
+        assert_eq!(restored.score(), gs.score());
+        assert_eq!(restored.combo(), 2);
+        assert_eq!(restored.rng_state(), gs.rng_state());
+
+        assert_eq!(
+            GameState::from_share_code(&code[..code.len() - 4]).err(),
+            Some(ParseError::InvalidShareCode)
+        );
+        assert!(GameState::from_share_code("not a code!").is_err());
+    }
+
+    #[test]
+    fn share_code_rejects_non_tetrominoes() {
+        let mut held = GameState::with_seed(1);
+        held.hold_piece = Some(PieceKind::Garbage);
+        let mut bagged = GameState::with_seed(1);
+        bagged.next_piece_bag.push(PieceKind::None);
+        for gs in [held, bagged] {
+            assert_eq!(
+                GameState::from_share_code(&gs.to_share_code()).err(),
+                Some(ParseError::InvalidShareCode)
+            );
+        }
+    }
+
+    #[test]
+    fn themed_render() {
+        let gs = GameState::default();
+        let theme = RenderTheme {
+            glyph: "X".to_string(),
+            colors: Default::default(),
+            ghost_color: String::new(),
+        };
+        assert_eq!(gs.render_with_theme(&theme), "XXXXXXXXXX\r\n".repeat(20));
+        let default = gs.render_with_theme(&RenderTheme::default());
+        assert!(default.contains(piece::BLOCK_STR) && !default.contains('X'));
+        assert_eq!(default, gs.to_string());
+    }
+
+    #[test]
+    fn lines_remaining_until_level_five() {
+        let mut gs = GameState::default();
+        assert_eq!(gs.lines_remaining_for_level(5), 40);
+        gs.score.lines_cleared = 3;
+        assert_eq!(gs.lines_remaining_for_level(5), 37);
+        assert_eq!(gs.lines_remaining_for_level(1), 0);
+    }
+
+    #[test]
+    fn garbage_preview_leaves_live_grid_untouched() {
+        let mut gs = GameState::default();
+        gs.queue_garbage(2, 3);
+
+        let preview = gs.preview_with_garbage();
+        for row in 0..2 {
+            for col in 0..GRID_COLUMNS {
+                let expected = if col == 3 {
+                    PieceKind::None
+                } else {
+                    PieceKind::Garbage
+                };
+                assert_eq!(preview.grid_map[row][col], expected);
+            }
+        }
+        assert_eq!(gs.grid.widths(), [0i32; GRID_ROWS]);
+
+        gs.apply_pending_garbage();
+        assert_eq!(gs.grid.grid_map, preview.grid_map);
+        assert!(gs.pending_garbage.is_empty());
     }
 }
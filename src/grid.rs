@@ -4,35 +4,72 @@ pub const GRID_COLUMNS: usize = 10;
 pub const GRID_ROWS: usize = 24;
 pub const GRID_VISIBLE_ROWS: usize = 20;
 
-type GridMap = [[PieceKind; GRID_COLUMNS]; GRID_ROWS];
+pub(crate) type GridMap<const COLS: usize = GRID_COLUMNS, const ROWS: usize = GRID_ROWS> =
+    [[PieceKind; COLS]; ROWS];
 
 #[derive(Debug, Clone)]
-pub struct Grid {
+pub struct Grid<const COLS: usize, const ROWS: usize> {
     // Map of the entire grid
-    pub grid_map: GridMap,
+    pub grid_map: GridMap<COLS, ROWS>,
 }
 
-impl Default for Grid {
+// The guideline sized board used by the game
+pub type StandardGrid = Grid<GRID_COLUMNS, GRID_ROWS>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+impl<const COLS: usize, const ROWS: usize> Default for Grid<COLS, ROWS> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl From<GridMap> for Grid {
-    fn from(map: GridMap) -> Self {
+impl<const COLS: usize, const ROWS: usize> From<GridMap<COLS, ROWS>> for Grid<COLS, ROWS> {
+    fn from(map: GridMap<COLS, ROWS>) -> Self {
         Self { grid_map: map }
     }
 }
 
-impl Grid {
+impl<const COLS: usize, const ROWS: usize> Grid<COLS, ROWS> {
+    // Rows above the visible ones are a spawn buffer as high as the standard one
+    pub const VISIBLE_ROWS: usize = ROWS.saturating_sub(GRID_ROWS - GRID_VISIBLE_ROWS);
+
     pub fn new() -> Self {
         Self {
-            grid_map: [[PieceKind::None; GRID_COLUMNS]; GRID_ROWS],
+            grid_map: [[PieceKind::None; COLS]; ROWS],
+        }
+    }
+
+    // Parses rows of piece characters (see `PieceKind::to_char`), given top
+    // to bottom with the last row being the floor. Returns None if a row has
+    // the wrong width, a character is unknown or there are too many rows.
+    pub fn from_ascii(rows: &[&str]) -> Option<Self> {
+        if rows.len() > ROWS {
+            return None;
+        }
+        let mut grid = Self::new();
+        for (y, row) in rows.iter().rev().enumerate() {
+            if row.chars().count() != COLS {
+                return None;
+            }
+            for (x, c) in row.chars().enumerate() {
+                grid.grid_map[y][x] = PieceKind::from_char(c)?;
+            }
         }
+        Some(grid)
+    }
+
+    // Visible rows in display order, top row first
+    pub fn rows_top_down(&self) -> impl Iterator<Item = &[PieceKind; COLS]> {
+        self.grid_map[..Self::VISIBLE_ROWS].iter().rev()
     }
 
-    pub fn widths(&self) -> [i32; GRID_ROWS] {
-        let mut result = [0i32; GRID_ROWS];
+    pub fn widths(&self) -> [i32; ROWS] {
+        let mut result = [0i32; ROWS];
         result.iter_mut().enumerate().for_each(|(row, width)| {
             *width = self.grid_map[row]
                 .iter()
@@ -45,12 +82,21 @@ impl Grid {
         result
     }
 
-    pub fn heights(&self, below_row: i32) -> [i32; GRID_COLUMNS] {
-        let mut result = [0i32; GRID_COLUMNS];
-        (0..GRID_COLUMNS).for_each(|col| {
+    pub fn full_rows(&self) -> Vec<usize> {
+        self.widths()
+            .iter()
+            .enumerate()
+            .filter(|(_, w)| **w == COLS as i32)
+            .map(|(row, _)| row)
+            .collect()
+    }
+
+    pub fn heights(&self, below_row: i32) -> [i32; COLS] {
+        let mut result = [0i32; COLS];
+        (0..COLS).for_each(|col| {
             result[col] = (0..below_row)
                 .rev()
-                .skip_while(|row| *row >= GRID_ROWS as i32)
+                .skip_while(|row| *row >= ROWS as i32)
                 .skip_while(|row| self.grid_map[*row as usize][col] == PieceKind::None)
                 .map(|row| row + 1)
                 .next()
@@ -59,12 +105,115 @@ impl Grid {
         result
     }
 
+    // Column heights capped at the visible field, small enough to send for a
+    // miniature opponent board
+    pub fn skyline(&self) -> [u8; COLS] {
+        self.heights(ROWS as i32)
+            .map(|height| height.min(Self::VISIBLE_ROWS as i32) as u8)
+    }
+
+    // Sum of all column heights
+    pub fn aggregate_height(&self) -> i32 {
+        self.heights(ROWS as i32).iter().sum()
+    }
+
+    // Index and height of the shortest column, the leftmost one on ties
+    pub fn lowest_column(&self) -> (usize, i32) {
+        self.heights(ROWS as i32)
+            .into_iter()
+            .enumerate()
+            .min_by_key(|(_, height)| *height)
+            .unwrap()
+    }
+
+    // Sum of the absolute height differences between neighbouring columns
+    pub fn bumpiness(&self) -> i32 {
+        self.heights(ROWS as i32)
+            .windows(2)
+            .map(|pair| (pair[0] - pair[1]).abs())
+            .sum()
+    }
+
+    // Depth of the deepest one column wide well that only a vertical I can
+    // fill, i.e. one at least three cells deep. Walls count as infinitely
+    // high. Returns 0 if there is no such well.
+    pub fn ipiece_dependency_depth(&self) -> i32 {
+        let heights = self.heights(ROWS as i32);
+        (0..COLS)
+            .map(|col| {
+                let left = if col == 0 { i32::MAX } else { heights[col - 1] };
+                let right = heights.get(col + 1).copied().unwrap_or(i32::MAX);
+                left.min(right) - heights[col]
+            })
+            .filter(|depth| *depth >= 3)
+            .max()
+            .unwrap_or(0)
+    }
+
+    // (column, row) of every empty cell with a block somewhere above it,
+    // column by column from the bottom up
+    pub fn hole_positions(&self) -> Vec<(usize, usize)> {
+        let heights = self.heights(ROWS as i32);
+        (0..COLS)
+            .flat_map(|col| {
+                (0..heights[col] as usize)
+                    .filter(move |row| self.grid_map[*row][col] == PieceKind::None)
+                    .map(move |row| (col, row))
+            })
+            .collect()
+    }
+
+    // Half of the board to keep the well on: the one whose columns are lower
+    // in total. Ties go to the right.
+    pub fn recommended_well_side(&self) -> Side {
+        let heights = self.heights(ROWS as i32);
+        let left: i32 = heights[..COLS / 2].iter().sum();
+        let right: i32 = heights[COLS.div_ceil(2)..].iter().sum();
+        if left < right {
+            Side::Left
+        } else {
+            Side::Right
+        }
+    }
+
+    // Number of rows, counted up from the floor, that contain no holes.
+    // An empty cell is a hole when there is a block somewhere above it.
+    pub fn clean_rows_from_bottom(&self) -> usize {
+        let heights = self.heights(ROWS as i32);
+        (0..ROWS)
+            .take_while(|row| {
+                (0..COLS).all(|col| {
+                    self.grid_map[*row][col] != PieceKind::None || *row as i32 >= heights[col]
+                })
+            })
+            .count()
+    }
+
+    // Shannon entropy (in bits) of the differences between adjacent column
+    // heights. A flat or evenly sloped surface has zero entropy.
+    pub fn entropy(&self) -> f32 {
+        let heights = self.heights(ROWS as i32);
+        let diffs: Vec<i32> = heights.windows(2).map(|w| w[1] - w[0]).collect();
+        let mut counts: Vec<(i32, usize)> = Vec::new();
+        for diff in &diffs {
+            match counts.iter_mut().find(|(value, _)| value == diff) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((*diff, 1)),
+            }
+        }
+        counts
+            .iter()
+            .map(|(_, count)| *count as f32 / diffs.len() as f32)
+            .map(|p| -p * p.log2())
+            .sum()
+    }
+
     pub fn is_within_bounds(x: i32, y: i32) -> bool {
-        0 <= x && x < GRID_COLUMNS as i32 && 0 <= y && y < GRID_ROWS as i32
+        0 <= x && x < COLS as i32 && 0 <= y && y < ROWS as i32
     }
 
     pub fn is_rect_inside(x_min: i32, x_max: i32, y_min: i32, y_max: i32) -> bool {
-        0 <= x_min && x_max < GRID_COLUMNS as i32 && 0 <= y_min && y_max < GRID_ROWS as i32
+        0 <= x_min && x_max < COLS as i32 && 0 <= y_min && y_max < ROWS as i32
     }
 
     pub fn set_cell(&mut self, x: i32, y: i32, kind: PieceKind) {
@@ -83,9 +232,63 @@ impl Grid {
         self.grid_map[y as usize][x as usize]
     }
 
+    // Writes the cells of `piece` into the grid at its current position
+    pub fn place_piece(&mut self, piece: &Piece) {
+        let (x, y) = (piece.position.x, piece.position.y);
+        piece
+            .piece_dimensions
+            .piece_map
+            .iter()
+            .for_each(|(px, py)| self.set_cell(x + px, y + py, piece.kind));
+    }
+
     pub fn clear_row(&mut self, row: usize) {
-        assert!(row < GRID_ROWS, "Row {} out of bounds", row);
-        (0..GRID_COLUMNS).for_each(|col| self.grid_map[row][col] = PieceKind::None)
+        assert!(row < ROWS, "Row {} out of bounds", row);
+        (0..COLS).for_each(|col| self.grid_map[row][col] = PieceKind::None)
+    }
+
+    // Removes the given rows and moves everything above them down
+    pub fn clear_rows(&mut self, rows: &[usize]) {
+        rows.iter()
+            .for_each(|row| assert!(*row < ROWS, "Row {} out of bounds", row));
+        let mut new_map = [[PieceKind::None; COLS]; ROWS];
+        (0..ROWS)
+            .filter(|row| !rows.contains(row))
+            .enumerate()
+            .for_each(|(new_row, row)| new_map[new_row] = self.grid_map[row]);
+        self.grid_map = new_map;
+    }
+
+    // Drops every block straight down its column until it rests on the floor
+    // or another block, closing any gaps underneath it.
+    pub fn apply_cascade_gravity(&mut self) {
+        (0..COLS).for_each(|col| {
+            let mut landing_row = 0;
+            for row in 0..ROWS {
+                let kind = self.grid_map[row][col];
+                if kind != PieceKind::None {
+                    self.grid_map[row][col] = PieceKind::None;
+                    self.grid_map[landing_row][col] = kind;
+                    landing_row += 1;
+                }
+            }
+        })
+    }
+
+    // Pushes the stack up by `lines` rows and fills the bottom with garbage,
+    // leaving `hole_column` open. Returns true if blocks were pushed off the top.
+    pub fn add_garbage(&mut self, lines: usize, hole_column: usize) -> bool {
+        assert!(hole_column < COLS, "Column {} out of bounds", hole_column);
+        let lines = lines.min(ROWS);
+        let overflow = self.grid_map[ROWS - lines..]
+            .iter()
+            .any(|row| row.iter().any(|kind| *kind != PieceKind::None));
+        self.grid_map.copy_within(0..ROWS - lines, lines);
+        (0..lines).for_each(|row| {
+            self.grid_map[row] = [PieceKind::Garbage; COLS];
+            self.grid_map[row][hole_column] = PieceKind::None;
+        });
+        overflow
     }
 
     pub fn overlaps(&mut self, piece: &Piece) -> bool {
@@ -108,7 +311,7 @@ mod tests {
     #[test]
     fn empty_grid() {
         // Empty grid
-        let grid = Grid::new();
+        let grid = StandardGrid::new();
         // All widths should be 0
         assert_eq!(grid.widths(), [0i32; GRID_ROWS]);
         // All heights should be 0
@@ -119,7 +322,7 @@ mod tests {
     fn full_grid() {
         // Grid completely filled with I piece blocks
         let grid_map: GridMap = [[PieceKind::I; GRID_COLUMNS]; GRID_ROWS];
-        let grid = Grid::from(grid_map);
+        let grid = StandardGrid::from(grid_map);
         // All rows should be GRID_COLUMNS wide
         assert_eq!(grid.widths(), [GRID_COLUMNS as i32; GRID_ROWS]);
         // All columns should be GRID_ROWS high
@@ -129,43 +332,55 @@ mod tests {
         );
     }
 
+    #[test]
+    fn custom_board_size() {
+        let mut grid = Grid::<4, 6>::from_ascii(&["..I.", "IIII"]).unwrap();
+        assert_eq!(Grid::<4, 6>::VISIBLE_ROWS, 2);
+        assert_eq!(grid.heights(6), [1, 1, 2, 1]);
+        assert_eq!(grid.full_rows(), vec![0]);
+        grid.clear_rows(&[0]);
+        assert_eq!(grid.widths(), [1, 0, 0, 0, 0, 0]);
+        assert!(!Grid::<4, 6>::is_within_bounds(4, 0));
+        assert!(StandardGrid::from_ascii(&["IIII"]).is_none());
+    }
+
     #[test]
     fn bounds_checking() {
         // Check all positions that SHOULD be within bounds
         for x in 0..(GRID_COLUMNS as i32) {
             for y in 0..(GRID_ROWS as i32) {
-                assert!(Grid::is_within_bounds(x, y))
+                assert!(StandardGrid::is_within_bounds(x, y))
             }
         }
         // Test off-by-one cases (should be out of bounds)
-        assert!(!Grid::is_within_bounds(-1, 0));
-        assert!(!Grid::is_within_bounds(GRID_COLUMNS as i32, 0));
-        assert!(!Grid::is_within_bounds(0, -1));
-        assert!(!Grid::is_within_bounds(0, GRID_ROWS as i32));
+        assert!(!StandardGrid::is_within_bounds(-1, 0));
+        assert!(!StandardGrid::is_within_bounds(GRID_COLUMNS as i32, 0));
+        assert!(!StandardGrid::is_within_bounds(0, -1));
+        assert!(!StandardGrid::is_within_bounds(0, GRID_ROWS as i32));
         // Try valid rectangles with different x values but same y values
         for x1 in 0..(GRID_COLUMNS as i32) {
             for x2 in x1..(GRID_COLUMNS as i32) {
                 let (y1, y2) = (0i32, GRID_ROWS as i32 - 1);
-                assert!(Grid::is_rect_inside(x1, x2, y1, y2));
+                assert!(StandardGrid::is_rect_inside(x1, x2, y1, y2));
             }
         }
         // Try valid rectangles with different y values but same x values
         for y1 in 0..(GRID_ROWS as i32) {
             for y2 in y1..(GRID_ROWS as i32) {
                 let (x1, x2) = (0i32, GRID_COLUMNS as i32 - 1);
-                assert!(Grid::is_rect_inside(x1, x2, y1, y2));
+                assert!(StandardGrid::is_rect_inside(x1, x2, y1, y2));
             }
         }
         // Test off-by-one rectangles
-        assert!(!Grid::is_rect_inside(-1, 0, 0, 1));
-        assert!(!Grid::is_rect_inside(
+        assert!(!StandardGrid::is_rect_inside(-1, 0, 0, 1));
+        assert!(!StandardGrid::is_rect_inside(
             GRID_COLUMNS as i32 - 1,
             GRID_COLUMNS as i32,
             0,
             1
         ));
-        assert!(!Grid::is_rect_inside(0, 1, -1, 0));
-        assert!(!Grid::is_rect_inside(
+        assert!(!StandardGrid::is_rect_inside(0, 1, -1, 0));
+        assert!(!StandardGrid::is_rect_inside(
             0,
             1,
             GRID_ROWS as i32 - 1,
@@ -177,7 +392,7 @@ mod tests {
     fn row_clearing() {
         // Grid completely filled with I piece blocks
         let grid_map: GridMap = [[PieceKind::I; GRID_COLUMNS]; GRID_ROWS];
-        let mut grid = Grid::from(grid_map);
+        let mut grid = StandardGrid::from(grid_map);
         // Clear every row
         for row in 0..GRID_ROWS {
             grid.clear_row(row)
@@ -186,4 +401,156 @@ mod tests {
         assert_eq!(grid.widths(), [0i32; GRID_ROWS]);
         assert_eq!(grid.heights(GRID_ROWS as i32), [0i32; GRID_COLUMNS]);
     }
+
+    #[test]
+    fn entropy_of_flat_and_jagged_boards() {
+        let mut flat = StandardGrid::new();
+        flat.grid_map[0] = [PieceKind::I; GRID_COLUMNS];
+        let mut jagged = StandardGrid::new();
+        (0..GRID_COLUMNS).for_each(|col| {
+            (0..(col * 7 % 5)).for_each(|row| jagged.grid_map[row][col] = PieceKind::T)
+        });
+        assert_eq!(flat.entropy(), 0.0);
+        assert!(jagged.entropy() > flat.entropy());
+    }
+
+    #[test]
+    fn parsing_ascii_boards() {
+        let grid = StandardGrid::from_ascii(&["T.........", "GGGGGGGGG."]).unwrap();
+        assert_eq!(grid.get_cell(0, 1), PieceKind::T);
+        assert_eq!(grid.get_cell(1, 1), PieceKind::None);
+        assert_eq!(grid.widths()[0], GRID_COLUMNS as i32 - 1);
+        assert!(StandardGrid::from_ascii(&["T........"]).is_none());
+        assert!(StandardGrid::from_ascii(&["T........?"]).is_none());
+    }
+
+    #[test]
+    fn clean_rows_below_garbage() {
+        let grid =
+            StandardGrid::from_ascii(&["....T.....", "GGGG.GGGG.", "IIIIIIIII.", "IIIIIIIII."])
+                .unwrap();
+        assert_eq!(grid.clean_rows_from_bottom(), 2);
+        assert_eq!(StandardGrid::new().clean_rows_from_bottom(), GRID_ROWS);
+    }
+
+    #[test]
+    fn rows_in_display_order() {
+        let mut grid = StandardGrid::new();
+        grid.grid_map[GRID_VISIBLE_ROWS - 1][0] = PieceKind::T;
+        grid.grid_map[GRID_VISIBLE_ROWS][0] = PieceKind::I;
+        let rows: Vec<_> = grid.rows_top_down().collect();
+        assert_eq!(rows.len(), GRID_VISIBLE_ROWS);
+        assert_eq!(rows[0][0], PieceKind::T);
+        assert_eq!(rows[GRID_VISIBLE_ROWS - 1], &grid.grid_map[0]);
+    }
+
+    #[test]
+    fn staircase_height_and_bumpiness() {
+        let grid =
+            StandardGrid::from_ascii(&["...O......", "..OO......", ".OOO......", "OOOO......"])
+                .unwrap();
+        assert_eq!(grid.aggregate_height(), 1 + 2 + 3 + 4);
+        assert_eq!(grid.bumpiness(), 1 + 1 + 1 + 4);
+    }
+
+    #[test]
+    fn staircase_lowest_column() {
+        let grid =
+            StandardGrid::from_ascii(&["OOOOOO....", "OOOOOOO...", "OOOOOOOO..", "OOOOOOOOOO"])
+                .unwrap();
+        assert_eq!(grid.lowest_column(), (8, 1));
+        assert_eq!(StandardGrid::new().lowest_column(), (0, 0));
+    }
+
+    #[test]
+    fn skyline_clamps_heights() {
+        let mut grid =
+            StandardGrid::from_ascii(&["O.........", "O....I....", "O....I..T."]).unwrap();
+        grid.grid_map[GRID_ROWS - 1][9] = PieceKind::Z;
+        let expected = grid
+            .heights(GRID_ROWS as i32)
+            .map(|height| height.min(GRID_VISIBLE_ROWS as i32) as u8);
+        assert_eq!(grid.skyline(), expected);
+        assert_eq!(grid.skyline(), [3, 0, 0, 0, 0, 2, 0, 0, 1, 20]);
+    }
+
+    #[test]
+    fn flat_height_and_bumpiness() {
+        let grid = StandardGrid::from_ascii(&["OOOOOOOOO.", "OOOOOOOOO."]).unwrap();
+        // Only the empty last column breaks the surface
+        assert_eq!(grid.aggregate_height(), 18);
+        assert_eq!(grid.bumpiness(), 2);
+        assert_eq!(StandardGrid::new().aggregate_height(), 0);
+        assert_eq!(StandardGrid::new().bumpiness(), 0);
+    }
+
+    #[test]
+    fn ipiece_dependency() {
+        let mut grid =
+            StandardGrid::from_ascii(&["OO.OOOOOOO", "OO.OOOOOOO", "OO.OOOOOOO", "OO.OOOOOOO"])
+                .unwrap();
+        assert_eq!(grid.ipiece_dependency_depth(), 4);
+        // Two deep wells can be filled by other pieces
+        grid.clear_rows(&[0, 1]);
+        assert_eq!(grid.ipiece_dependency_depth(), 0);
+        assert_eq!(StandardGrid::new().ipiece_dependency_depth(), 0);
+    }
+
+    #[test]
+    fn garbage_fills_rows() {
+        let mut grid = StandardGrid::new();
+        grid.add_garbage(2, 4);
+        assert_eq!(grid.widths()[..3], [9, 9, 0]);
+        grid.set_cell(4, 1, PieceKind::T);
+        assert_eq!(grid.full_rows(), vec![1]);
+        grid.clear_rows(&[1]);
+        assert_eq!(grid.get_cell(0, 0), PieceKind::Garbage);
+        assert_eq!(grid.widths()[..2], [9, 0]);
+    }
+
+    #[test]
+    fn hole_positions() {
+        let grid =
+            StandardGrid::from_ascii(&["..O.....T.", "..O.....T.", "O.O.....TT", "O......T.T"])
+                .unwrap();
+        assert_eq!(grid.hole_positions(), vec![(2, 0), (8, 0)]);
+        assert!(StandardGrid::new().hole_positions().is_empty());
+    }
+
+    #[test]
+    fn well_side_follows_lower_half() {
+        let tall_left =
+            StandardGrid::from_ascii(&["OOO.......", "OOOOO.....", "OOOOOO.O.."]).unwrap();
+        assert_eq!(tall_left.recommended_well_side(), Side::Right);
+        let tall_right = StandardGrid::from_ascii(&["......OOOO", ".OO...OOOO"]).unwrap();
+        assert_eq!(tall_right.recommended_well_side(), Side::Left);
+        assert_eq!(StandardGrid::new().recommended_well_side(), Side::Right);
+    }
+
+    #[test]
+    fn clearing_selected_rows() {
+        let mut grid = StandardGrid::new();
+        let kinds = [
+            PieceKind::I,
+            PieceKind::J,
+            PieceKind::L,
+            PieceKind::O,
+            PieceKind::S,
+        ];
+        for (row, kind) in kinds.iter().enumerate() {
+            grid.grid_map[row] = [*kind; GRID_COLUMNS];
+        }
+        // Clear two rows that aren't next to each other
+        grid.clear_rows(&[1, 3]);
+        assert_eq!(grid.grid_map[0], [PieceKind::I; GRID_COLUMNS]);
+        assert_eq!(grid.grid_map[1], [PieceKind::L; GRID_COLUMNS]);
+        assert_eq!(grid.grid_map[2], [PieceKind::S; GRID_COLUMNS]);
+        assert_eq!(grid.widths()[3..], [0i32; GRID_ROWS - 3]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn clearing_rows_out_of_bounds() {
+        StandardGrid::new().clear_rows(&[0, GRID_ROWS]);
+    }
 }
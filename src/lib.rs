@@ -6,3 +6,4 @@ pub mod gamestate;
 pub mod game;
 pub mod timer;
 pub mod bot;
+pub mod replay;
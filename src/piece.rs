@@ -1,7 +1,7 @@
 use rand::seq::SliceRandom;
 use termion::color;
 
-use crate::grid::GRID_COLUMNS;
+use crate::grid::{GRID_COLUMNS, GRID_VISIBLE_ROWS};
 use crate::utils::{Direction, Rotation};
 
 use rand::{
@@ -12,6 +12,8 @@ use std::fmt;
 use std::mem;
 
 type PieceMap = [(i32, i32); 4];
+// How far into the buffer above the visible field the top of a new piece is
+const SPAWN_BUFFER_ROWS: i32 = 2;
 // Bit masks for each piece kind in its initial (unrotated) state.
 const PIECE_I: PieceMap = [(0, 1), (1, 1), (2, 1), (3, 1)];
 const PIECE_J: PieceMap = [(0, 1), (1, 1), (2, 1), (2, 0)];
@@ -30,9 +32,52 @@ pub enum PieceKind {
     S,
     T,
     Z,
+    Garbage,
     None,
 }
 
+impl PieceKind {
+    // Single character used for plain text boards
+    pub fn to_char(self) -> char {
+        match self {
+            PieceKind::I => 'I',
+            PieceKind::J => 'J',
+            PieceKind::L => 'L',
+            PieceKind::O => 'O',
+            PieceKind::S => 'S',
+            PieceKind::T => 'T',
+            PieceKind::Z => 'Z',
+            PieceKind::Garbage => 'G',
+            PieceKind::None => '.',
+        }
+    }
+
+    pub fn from_char(c: char) -> Option<Self> {
+        match c {
+            'I' => Some(PieceKind::I),
+            'J' => Some(PieceKind::J),
+            'L' => Some(PieceKind::L),
+            'O' => Some(PieceKind::O),
+            'S' => Some(PieceKind::S),
+            'T' => Some(PieceKind::T),
+            'Z' => Some(PieceKind::Z),
+            'G' => Some(PieceKind::Garbage),
+            '.' => Some(PieceKind::None),
+            _ => None,
+        }
+    }
+
+    // Inverse of `kind as u8`
+    pub fn from_index(index: u8) -> Option<Self> {
+        match index {
+            0..=6 => Some(PIECE_VEC[index as usize]),
+            7 => Some(PieceKind::Garbage),
+            8 => Some(PieceKind::None),
+            _ => None,
+        }
+    }
+}
+
 pub(crate) const PIECE_VEC: [PieceKind; 7] = [
     PieceKind::I,
     PieceKind::J,
@@ -43,30 +88,37 @@ pub(crate) const PIECE_VEC: [PieceKind; 7] = [
     PieceKind::Z,
 ];
 
-pub fn gen_piece_bag() -> [PieceKind; 7] {
-    let mut rng = rand::thread_rng();
+pub fn gen_piece_bag<R: Rng + ?Sized>(rng: &mut R) -> [PieceKind; 7] {
     let mut piece_bag = PIECE_VEC;
-    piece_bag.shuffle(&mut rng);
+    piece_bag.shuffle(rng);
     piece_bag
 }
 
 pub const BLOCK_STR: &str = "■";
 
-impl fmt::Display for PieceKind {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl PieceKind {
+    // Escape code setting the default foreground color of the kind
+    pub fn color(self) -> String {
         match self {
-            PieceKind::I => write!(f, "{}{}", color::Fg(color::LightBlue), BLOCK_STR),
-            PieceKind::J => write!(f, "{}{}", color::Fg(color::Rgb(75, 0, 130)), BLOCK_STR),
-            PieceKind::L => write!(f, "{}{}", color::Fg(color::Rgb(255, 100, 0)), BLOCK_STR),
-            PieceKind::O => write!(f, "{}{}", color::Fg(color::Yellow), BLOCK_STR),
-            PieceKind::S => write!(f, "{}{}", color::Fg(color::LightGreen), BLOCK_STR),
-            PieceKind::T => write!(f, "{}{}", color::Fg(color::Magenta), BLOCK_STR),
-            PieceKind::Z => write!(f, "{}{}", color::Fg(color::Red), BLOCK_STR),
-            PieceKind::None => write!(f, "{}{}", color::Fg(color::LightWhite), BLOCK_STR),
+            PieceKind::I => color::Fg(color::LightBlue).to_string(),
+            PieceKind::J => color::Fg(color::Rgb(75, 0, 130)).to_string(),
+            PieceKind::L => color::Fg(color::Rgb(255, 100, 0)).to_string(),
+            PieceKind::O => color::Fg(color::Yellow).to_string(),
+            PieceKind::S => color::Fg(color::LightGreen).to_string(),
+            PieceKind::T => color::Fg(color::Magenta).to_string(),
+            PieceKind::Z => color::Fg(color::Red).to_string(),
+            PieceKind::Garbage => color::Fg(color::Rgb(100, 100, 100)).to_string(),
+            PieceKind::None => color::Fg(color::LightWhite).to_string(),
         }
     }
 }
 
+impl fmt::Display for PieceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.color(), BLOCK_STR)
+    }
+}
+
 impl Distribution<PieceKind> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> PieceKind {
         match rng.gen_range(0..=6) {
@@ -126,7 +178,7 @@ impl PieceDimensions {
     pub fn y_max(piece_map: PieceMap) -> i32 {
         piece_map
             .iter()
-            .max_by(|(y1, _), (_, y2)| y1.cmp(y2))
+            .max_by(|(_, y1), (_, y2)| y1.cmp(y2))
             .unwrap()
             .1
     }
@@ -192,6 +244,10 @@ pub struct Piece {
     pub rotation: Rotation,
     pub rotated_pieces: [PieceMap; 4],
     pub position: GridPosition,
+    // Unique id assigned by the game state when the piece spawns
+    pub piece_id: u64,
+    // Lowest and highest cell of every column, per rotation (see `column_spans`)
+    rotated_spans: [[(i32, i32); 4]; 4],
 }
 
 impl fmt::Debug for Piece {
@@ -215,6 +271,11 @@ impl fmt::Debug for Piece {
 
 impl Piece {
     pub fn new(kind: PieceKind) -> Self {
+        Self::spawned_in(kind, GRID_COLUMNS, GRID_VISIBLE_ROWS)
+    }
+
+    // A new piece centered above the visible rows of a grid `columns` wide
+    pub fn spawned_in(kind: PieceKind, columns: usize, visible_rows: usize) -> Self {
         let piece_dimensions: PieceDimensions;
         let origin: (f32, f32);
         match kind {
@@ -248,18 +309,40 @@ impl Piece {
             }
             _ => panic!("Invalid piece type: {:?}", kind),
         };
-        let xpos = GRID_COLUMNS as i32 / 2 - piece_dimensions.width / 2;
-        let ypos =
-            23 - piece_dimensions.height - PieceDimensions::y_min(piece_dimensions.piece_map); //20 - PieceDimensions::y_min(piece_dimensions.piece_map);
+        let xpos = columns as i32 / 2 - piece_dimensions.width / 2;
+        let ypos = visible_rows as i32 + SPAWN_BUFFER_ROWS
+            - PieceDimensions::y_max(piece_dimensions.piece_map);
+        let rotated_pieces = piece_dimensions.get_rotated_piece_maps(origin);
         Piece {
             kind,
-            rotated_pieces: piece_dimensions.get_rotated_piece_maps(origin),
+            rotated_spans: rotated_pieces.map(Self::spans_of),
+            rotated_pieces,
             piece_dimensions,
             rotation: Rotation::Rot0,
             position: GridPosition { x: xpos, y: ypos },
+            piece_id: 0,
         }
     }
 
+    // Entries past the width of the piece are left empty
+    fn spans_of(piece_map: PieceMap) -> [(i32, i32); 4] {
+        let x_min = PieceDimensions::x_min(piece_map);
+        let mut spans = [(i32::MAX, i32::MIN); 4];
+        piece_map.iter().for_each(|(x, y)| {
+            let span = &mut spans[(x - x_min) as usize];
+            *span = (span.0.min(*y), span.1.max(*y));
+        });
+        spans
+    }
+
+    // Lowest and highest y of each column the piece covers in its current
+    // rotation, from the leftmost column to the rightmost, relative to its
+    // position. Tetrominoes have no gaps within a column, so every cell
+    // between the two belongs to the piece.
+    pub fn column_spans(&self) -> &[(i32, i32)] {
+        &self.rotated_spans[self.rotation as usize][..self.piece_dimensions.width as usize]
+    }
+
     pub fn rotate(&mut self, rot: Rotation) {
         self.rotation += rot;
         self.piece_dimensions = PieceDimensions::new(self.rotated_pieces[self.rotation as usize]);
@@ -279,6 +362,7 @@ impl Piece {
 
     pub fn move_piece(&mut self, direction: Direction) {
         match direction {
+            Direction::Up => self.position.y += 1,
             Direction::Down => self.position.y -= 1,
             Direction::Left => self.position.x -= 1,
             Direction::Right => self.position.x += 1,
@@ -302,3 +386,64 @@ impl Piece {
         (self.x_min(), self.x_max(), self.y_min(), self.y_max())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sampling_only_yields_tetrominoes() {
+        let mut rng = rand::thread_rng();
+        assert!((0..500)
+            .map(|_| rng.gen::<PieceKind>())
+            .all(|kind| PIECE_VEC.contains(&kind)));
+    }
+
+    #[test]
+    fn piece_heights() {
+        let expected = [
+            (PIECE_I, 1),
+            (PIECE_J, 2),
+            (PIECE_L, 2),
+            (PIECE_O, 2),
+            (PIECE_S, 2),
+            (PIECE_T, 2),
+            (PIECE_Z, 2),
+        ];
+        for (piece_map, height) in expected {
+            assert_eq!(PieceDimensions::get_height(piece_map), height);
+        }
+        // Standing upright the long pieces are taller than they are wide
+        assert_eq!(
+            PieceDimensions::get_height([(1, 0), (1, 1), (1, 2), (1, 3)]),
+            4
+        );
+        assert_eq!(
+            PieceDimensions::get_height([(1, 0), (1, 1), (1, 2), (2, 2)]),
+            3
+        );
+    }
+
+    #[test]
+    fn pieces_spawn_above_visible_field() {
+        for kind in PIECE_VEC {
+            let piece = Piece::new(kind);
+            assert_eq!(piece.y_max(), GRID_VISIBLE_ROWS as i32 + 2);
+            assert!(piece.y_min() >= GRID_VISIBLE_ROWS as i32);
+        }
+    }
+
+    #[test]
+    fn l_piece_column_spans() {
+        let mut piece = Piece::new(PieceKind::L);
+        assert_eq!(piece.column_spans(), [(0, 1), (1, 1), (1, 1)]);
+        // The cached spans follow the rotation
+        piece.rotate_clockwise();
+        let spans = piece.column_spans();
+        assert_eq!(spans.len() as i32, piece.piece_dimensions.width);
+        assert_eq!(
+            spans,
+            &Piece::spans_of(piece.piece_dimensions.piece_map)[..spans.len()]
+        );
+    }
+}
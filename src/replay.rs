@@ -0,0 +1,124 @@
+use crate::controls::Button;
+use crate::gamestate::GameState;
+
+use std::time::Duration;
+
+// Game time that passes every frame, about 60 frames per second
+pub const FRAME_MS: u32 = 16;
+
+// The inputs of a game, frame by frame. Playing them back on a game with the
+// same seed reproduces it exactly.
+#[derive(Debug, Clone, Default)]
+pub struct Replay {
+    pub seed: u64,
+    // `GameState::line_clear_delay` the game was played with
+    pub line_clear_delay: Duration,
+    // Frame each button was pressed at, in the order they were pressed
+    pub inputs: Vec<(u32, Button)>,
+}
+
+impl Replay {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            line_clear_delay: Duration::ZERO,
+            inputs: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, frame: u32, button: Button) {
+        self.inputs.push((frame, button));
+    }
+
+    // Runs one frame: the buttons pressed during it first, then a
+    // `GameState::step` of `FRAME_MS`
+    pub fn step_frame(game_state: &mut GameState, buttons: impl IntoIterator<Item = Button>) {
+        buttons
+            .into_iter()
+            .for_each(|button| game_state.on_button_pressed(button));
+        game_state.step(Duration::from_millis(FRAME_MS as u64));
+    }
+
+    // The game after its first `frame` frames
+    pub fn state_at(&self, frame: u32) -> GameState {
+        let mut game_state = GameState::with_seed(self.seed);
+        game_state.line_clear_delay = self.line_clear_delay;
+        for current in 0..frame {
+            let buttons = self
+                .inputs
+                .iter()
+                .filter(|(at, _)| *at == current)
+                .map(|(_, button)| *button);
+            Self::step_frame(&mut game_state, buttons);
+        }
+        game_state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bot::best_move;
+
+    #[test]
+    fn scrubbing_matches_forward_run() {
+        let mut replay = Replay::new(11);
+        let mut forward = GameState::with_seed(11);
+        let mut mid_frame = None;
+        for frame in 0..300 {
+            let buttons = match frame % 40 {
+                0 => vec![Button::MoveLeft, Button::RotateClockwise],
+                10 => vec![Button::MoveRight],
+                25 => vec![Button::Drop],
+                _ => Vec::new(),
+            };
+            buttons
+                .iter()
+                .for_each(|button| replay.record(frame, *button));
+            Replay::step_frame(&mut forward, buttons);
+            if frame == 137 {
+                mid_frame = Some(forward.clone());
+            }
+        }
+        let mid_frame = mid_frame.unwrap();
+        let scrubbed = replay.state_at(138);
+        assert!(mid_frame.stats.pieces_placed > 0);
+        assert_eq!(scrubbed.to_string_repr(), mid_frame.to_string_repr());
+        assert_eq!(scrubbed.score(), mid_frame.score());
+        assert_eq!(
+            replay.state_at(300).to_string_repr(),
+            forward.to_string_repr()
+        );
+    }
+
+    #[test]
+    fn replays_line_clear_delay_in_game_time() {
+        let mut replay = Replay::new(5);
+        replay.line_clear_delay = Duration::from_millis(100);
+        let mut forward = GameState::with_seed(5);
+        forward.line_clear_delay = replay.line_clear_delay;
+        let (mut clears, mut clearing_frames) = (0, 0);
+        for frame in 0..2000 {
+            let buttons = match forward.clearing_rows().is_empty() && frame % 10 == 0 {
+                true => best_move(&forward),
+                false => Vec::new(),
+            };
+            buttons
+                .iter()
+                .for_each(|button| replay.record(frame, *button));
+            let was_clearing = !forward.clearing_rows().is_empty();
+            Replay::step_frame(&mut forward, buttons);
+            if !forward.clearing_rows().is_empty() {
+                clears += !was_clearing as u32;
+                clearing_frames += 1;
+            }
+        }
+        assert!(clears > 0);
+        // 100ms of game time runs out on the seventh frame of a clear, however
+        // long the frames really took
+        assert_eq!(clearing_frames, 6 * clears);
+        let replayed = replay.state_at(2000);
+        assert_eq!(replayed.to_string_repr(), forward.to_string_repr());
+        assert_eq!(replayed.score(), forward.score());
+    }
+}
@@ -8,6 +8,8 @@ pub enum Mode {
 pub struct Timer {
     duration: Duration,
     start_time: Option<Instant>,
+    // Time elapsed before the timer was last paused
+    accumulated: Duration,
     mode: Mode,
     running: bool,
 }
@@ -17,41 +19,180 @@ impl Timer {
         Self {
             duration,
             start_time: None,
+            accumulated: Duration::ZERO,
             mode,
             running: false,
         }
     }
 
     pub fn start(&mut self) {
+        self.start_at(Instant::now());
+    }
+
+    fn start_at(&mut self, now: Instant) {
         self.running = true;
-        self.start_time = Some(Instant::now());
+        self.start_time = Some(now);
+        self.accumulated = Duration::ZERO;
     }
 
-    pub fn finished(&mut self) -> bool {
-        if !(self.running && self.start_time.is_some()) {
-            false
-        } else if self.start_time.unwrap().elapsed() >= self.duration {
-            if let Mode::Repeating = self.mode {
-                self.update()
-            }
-            true
-        } else {
-            false
+    // Stops the timer and forgets all elapsed time
+    pub fn reset(&mut self) {
+        self.running = false;
+        self.start_time = None;
+        self.accumulated = Duration::ZERO;
+    }
+
+    pub fn restart(&mut self) {
+        self.restart_at(Instant::now());
+    }
+
+    fn restart_at(&mut self, now: Instant) {
+        self.reset();
+        self.start_at(now);
+    }
+
+    // Stops the clock, keeping the time elapsed so far
+    pub fn pause(&mut self) {
+        self.pause_at(Instant::now());
+    }
+
+    fn pause_at(&mut self, now: Instant) {
+        if let Some(start) = self.start_time.take() {
+            self.accumulated += now.saturating_duration_since(start);
+        }
+        self.running = false;
+    }
+
+    // Continues a paused timer from where it left off
+    pub fn resume(&mut self) {
+        self.resume_at(Instant::now());
+    }
+
+    fn resume_at(&mut self, now: Instant) {
+        if !self.running {
+            self.running = true;
+            self.start_time = Some(now);
+        }
+    }
+
+    // Number of whole periods that have passed, 0 if the timer hasn't
+    // finished yet. A repeating timer moves on to its current period.
+    pub fn finished(&mut self) -> u32 {
+        self.finished_at(Instant::now())
+    }
+
+    fn finished_at(&mut self, now: Instant) -> u32 {
+        match (self.running, self.start_time) {
+            (true, Some(_)) => match self.mode {
+                Mode::Repeating => self.update_at(now),
+                Mode::Once => self.elapsed_periods(now),
+            },
+            _ => 0,
         }
     }
 
     pub fn time_left(&mut self) -> Duration {
-        if self.running {
-            self.duration - self.start_time.unwrap().elapsed()
+        self.time_left_at(Instant::now())
+    }
+
+    fn time_left_at(&self, now: Instant) -> Duration {
+        self.duration.saturating_sub(self.elapsed(now))
+    }
+
+    // Advances the start time by whole periods until it is less than one
+    // period behind now, returning how many periods it skipped
+    pub fn update(&mut self) -> u32 {
+        self.update_at(Instant::now())
+    }
+
+    fn update_at(&mut self, now: Instant) -> u32 {
+        let periods = self.elapsed_periods(now);
+        let skipped = self.duration * periods;
+        // Skipped time comes out of the paused portion first
+        let from_accumulated = skipped.min(self.accumulated);
+        self.accumulated -= from_accumulated;
+        if let Some(start) = self.start_time.as_mut() {
+            *start += skipped - from_accumulated;
+        }
+        periods
+    }
+
+    // The clock is passed in so tests can run the timer without sleeping
+    fn elapsed(&self, now: Instant) -> Duration {
+        self.accumulated
+            + self
+                .start_time
+                .map_or(Duration::ZERO, |start| now.saturating_duration_since(start))
+    }
+
+    fn elapsed_periods(&self, now: Instant) -> u32 {
+        if self.duration.is_zero() {
+            0
         } else {
-            self.duration
+            (self.elapsed(now).as_nanos() / self.duration.as_nanos()) as u32
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn stall_over_several_periods() {
+        let mut timer = Timer::new(ms(20), Mode::Repeating);
+        let t0 = Instant::now();
+        timer.start_at(t0);
+        assert_eq!(timer.finished_at(t0), 0);
+        assert_eq!(timer.finished_at(t0 + ms(50)), 2);
+        assert_eq!(timer.time_left_at(t0 + ms(50)), ms(10));
+        assert_eq!(timer.finished_at(t0 + ms(55)), 0);
+        assert_eq!(timer.finished_at(t0 + ms(60)), 1);
+    }
+
+    #[test]
+    fn once_timer_keeps_counting() {
+        let mut timer = Timer::new(ms(10), Mode::Once);
+        let t0 = Instant::now();
+        assert_eq!(timer.finished_at(t0), 0);
+        timer.start_at(t0);
+        assert_eq!(timer.finished_at(t0 + ms(25)), 2);
+        assert_eq!(timer.finished_at(t0 + ms(25)), 2);
+        assert_eq!(timer.time_left_at(t0 + ms(25)), Duration::ZERO);
+    }
+
+    #[test]
+    fn pause_keeps_elapsed_time() {
+        let mut timer = Timer::new(ms(100), Mode::Once);
+        let t0 = Instant::now();
+        timer.start_at(t0);
+        timer.pause_at(t0 + ms(30));
+        assert_eq!(timer.time_left_at(t0 + ms(30)), ms(70));
+        assert_eq!(timer.time_left_at(t0 + ms(80)), ms(70));
+        assert_eq!(timer.finished_at(t0 + ms(200)), 0);
+        timer.resume_at(t0 + ms(80));
+        assert_eq!(timer.time_left_at(t0 + ms(100)), ms(50));
+        assert_eq!(timer.finished_at(t0 + ms(149)), 0);
+        assert_eq!(timer.finished_at(t0 + ms(150)), 1);
+    }
+
+    #[test]
+    fn restart_starts_from_zero() {
+        let mut timer = Timer::new(ms(10), Mode::Once);
+        let t0 = Instant::now();
+        timer.start_at(t0);
+        assert_eq!(timer.finished_at(t0 + ms(15)), 1);
+        timer.restart_at(t0 + ms(15));
+        assert_eq!(timer.finished_at(t0 + ms(15)), 0);
+        assert_eq!(timer.time_left_at(t0 + ms(20)), ms(5));
 
-    pub fn update(&mut self) {
-        self.start_time = Some(Instant::now()
-            + Duration::from_nanos(
-                self.start_time.unwrap().elapsed().as_nanos() as u64 % self.duration.as_nanos() as u64,
-            ));
+        timer.pause_at(t0 + ms(20));
+        timer.reset();
+        assert_eq!(timer.finished_at(t0 + ms(40)), 0);
+        assert_eq!(timer.time_left_at(t0 + ms(40)), ms(10));
     }
 }
@@ -1,5 +1,3 @@
-use std::convert::TryFrom;
-
 #[derive(Debug, Clone, Copy)]
 pub enum Rotation {
     Rot0,
@@ -10,6 +8,7 @@ pub enum Rotation {
 
 #[derive(Debug, Clone, Copy)]
 pub enum Direction {
+    Up,
     Down,
     Left,
     Right,
@@ -28,6 +27,39 @@ impl From<i32> for Rotation {
     }
 }
 
+impl Rotation {
+    // Clockwise angle from the spawn orientation
+    pub fn degrees(&self) -> u16 {
+        *self as u16 * 90
+    }
+
+    // Orientation label used by SRS tools such as fumen: 0, R, 2 or L
+    pub fn to_srs_label(self) -> char {
+        match self {
+            Rotation::Rot0 => '0',
+            Rotation::Rot90 => 'R',
+            Rotation::Rot180 => '2',
+            Rotation::Rot270 => 'L',
+        }
+    }
+
+    pub fn from_srs_label(label: char) -> Option<Rotation> {
+        match label {
+            '0' => Some(Rotation::Rot0),
+            'R' => Some(Rotation::Rot90),
+            '2' => Some(Rotation::Rot180),
+            'L' => Some(Rotation::Rot270),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Rotation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}°", self.degrees())
+    }
+}
+
 impl std::ops::Add for Rotation {
     type Output = Self;
     fn add(self, rhs: Self) -> Self::Output {
@@ -53,3 +85,132 @@ impl std::ops::SubAssign for Rotation {
         *self = Self::from(*self as i32 - rhs as i32)
     }
 }
+
+// SplitMix64 generator. Its whole state is a single u64, so games can save,
+// restore and reproduce the exact piece sequence they are going to see.
+#[derive(Debug, Clone)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn set_state(&mut self, state: u64) {
+        self.state = state;
+    }
+}
+
+impl rand::RngCore for SplitMix64 {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        dest.chunks_mut(8).for_each(|chunk| {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        });
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl rand::SeedableRng for SplitMix64 {
+    type Seed = [u8; 8];
+
+    fn from_seed(seed: Self::Seed) -> Self {
+        Self {
+            state: u64::from_le_bytes(seed),
+        }
+    }
+
+    fn seed_from_u64(state: u64) -> Self {
+        Self { state }
+    }
+}
+
+const BASE64_URL_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+// URL-safe base64 without padding
+pub fn encode_base64_url(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let mut buf = [0u8; 3];
+        buf[..chunk.len()].copy_from_slice(chunk);
+        let n = u32::from_be_bytes([0, buf[0], buf[1], buf[2]]);
+        (0..chunk.len() + 1).for_each(|i| {
+            out.push(BASE64_URL_ALPHABET[((n >> (18 - 6 * i)) & 63) as usize] as char)
+        });
+    }
+    out
+}
+
+// Inverse of `encode_base64_url`, None on characters outside the alphabet
+// or a length no encoding produces
+pub fn decode_base64_url(s: &str) -> Option<Vec<u8>> {
+    let digits = s
+        .bytes()
+        .map(|c| {
+            BASE64_URL_ALPHABET
+                .iter()
+                .position(|a| *a == c)
+                .map(|d| d as u32)
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let mut out = Vec::with_capacity(digits.len() * 3 / 4);
+    for chunk in digits.chunks(4) {
+        if chunk.len() == 1 {
+            return None;
+        }
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0, |n, (i, d)| n | d << (18 - 6 * i));
+        out.extend_from_slice(&n.to_be_bytes()[1..chunk.len()]);
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotation_degrees() {
+        let rotations = [
+            Rotation::Rot0,
+            Rotation::Rot90,
+            Rotation::Rot180,
+            Rotation::Rot270,
+        ];
+        assert_eq!(rotations.map(|rot| rot.degrees()), [0, 90, 180, 270]);
+        assert_eq!((Rotation::Rot90 + Rotation::Rot270).degrees(), 0);
+        assert_eq!(Rotation::Rot180.to_string(), "180°");
+    }
+
+    #[test]
+    fn srs_labels_round_trip() {
+        for rotation in (0..4).map(Rotation::from) {
+            let label = rotation.to_srs_label();
+            let parsed = Rotation::from_srs_label(label).unwrap();
+            assert_eq!(parsed as i32, rotation as i32);
+        }
+        assert_eq!(Rotation::Rot270.to_srs_label(), 'L');
+        assert!(Rotation::from_srs_label('r').is_none());
+    }
+}